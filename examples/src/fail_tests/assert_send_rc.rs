@@ -0,0 +1,11 @@
+use ouroboros::self_referencing;
+use std::rc::Rc;
+
+#[self_referencing(assert_send)]
+struct AssertSendRc {
+    data: Rc<i32>,
+    #[borrows(data)]
+    dref: &'this i32,
+}
+
+fn main() { }
@@ -0,0 +1,10 @@
+use ouroboros::self_referencing;
+
+#[self_referencing]
+struct BorrowsInCfgAttr {
+    data: Box<i32>,
+    #[cfg_attr(all(), borrows(data))]
+    dref: &'this i32,
+}
+
+fn main() { }
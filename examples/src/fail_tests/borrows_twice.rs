@@ -0,0 +1,12 @@
+use ouroboros::self_referencing;
+
+#[self_referencing]
+struct BorrowsTwice {
+    a: Box<i32>,
+    b: Box<i32>,
+    #[borrows(a)]
+    #[borrows(b)]
+    sum: i32,
+}
+
+fn main() { }
@@ -0,0 +1,13 @@
+use ouroboros::self_referencing;
+
+#[self_referencing]
+struct Chain {
+    text: String,
+    #[borrows(text)]
+    #[covariant]
+    words: Vec<&'this str>,
+    #[borrows(words)]
+    first: &'this str,
+}
+
+fn main() {}
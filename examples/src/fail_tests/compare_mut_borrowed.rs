@@ -0,0 +1,10 @@
+use ouroboros::self_referencing;
+
+#[self_referencing(derive(PartialEq))]
+struct CompareMutBorrowed {
+    data: Vec<i32>,
+    #[borrows(mut data)]
+    dref: &'this mut Vec<i32>,
+}
+
+fn main() { }
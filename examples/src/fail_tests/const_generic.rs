@@ -0,0 +1,10 @@
+use ouroboros::self_referencing;
+
+#[self_referencing]
+struct ConstGeneric<const N: usize> {
+    data: Box<[i32; N]>,
+    #[borrows(data)]
+    first: &'this i32,
+}
+
+fn main() {}
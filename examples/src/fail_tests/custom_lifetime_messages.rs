@@ -0,0 +1,19 @@
+use ouroboros::self_referencing;
+
+#[self_referencing(lifetime = 'own)]
+struct UnknownCovariance {
+    data: Box<i32>,
+    #[borrows(data)]
+    dref: std::cell::Cell<&'own i32>,
+}
+
+#[self_referencing(lifetime = 'own)]
+struct DerefNonReference {
+    data: Box<i32>,
+    #[borrows(data)]
+    #[covariant]
+    #[deref]
+    dref: Option<&'own i32>,
+}
+
+fn main() { }
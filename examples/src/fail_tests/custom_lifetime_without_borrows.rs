@@ -0,0 +1,9 @@
+use ouroboros::self_referencing;
+
+#[self_referencing(lifetime = 'own)]
+struct CustomLifetimeWithoutBorrows {
+    data: Box<i32>,
+    dref: &'own i32,
+}
+
+fn main() { }
@@ -0,0 +1,24 @@
+#![deny(deprecated)]
+
+use ouroboros::self_referencing;
+
+#[self_referencing(debug_expand)]
+struct DebugExpand {
+    data: Box<i32>,
+    #[borrows(data)]
+    dref: &'this i32,
+}
+
+mod other {
+    use ouroboros::self_referencing;
+
+    // Gets its own file even though it has the same name.
+    #[self_referencing(debug_expand)]
+    struct DebugExpand {
+        data: Box<i32>,
+        #[borrows(data)]
+        dref: &'this i32,
+    }
+}
+
+fn main() { }
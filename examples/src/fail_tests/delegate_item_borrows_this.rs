@@ -0,0 +1,21 @@
+use ouroboros::self_referencing;
+
+#[self_referencing]
+struct Words {
+    text: Box<str>,
+    #[borrows(text)]
+    #[not_covariant]
+    #[delegate(Iterator)]
+    words: std::str::Split<'this, char>,
+}
+
+#[self_referencing]
+struct ExplicitWords {
+    text: Box<str>,
+    #[borrows(text)]
+    #[not_covariant]
+    #[delegate(Iterator<Item = &'this str>)]
+    words: std::str::Split<'this, char>,
+}
+
+fn main() { }
@@ -0,0 +1,12 @@
+use ouroboros::self_referencing;
+use std::io;
+
+#[self_referencing]
+struct OtherRead {
+    data: Box<[u8]>,
+    #[borrows(data)]
+    #[delegate(io::Read)]
+    reader: &'this [u8],
+}
+
+fn main() { }
@@ -0,0 +1,40 @@
+#![deny(deprecated)]
+
+use ouroboros::self_referencing;
+
+#[self_referencing]
+struct DeprecatedAccessor {
+    data: Box<i32>,
+    #[borrows(data)]
+    #[accessor_attrs(deprecated(note = "use borrow_data instead"))]
+    dref: &'this i32,
+}
+
+fn log_drop(_fields: ouroboros_impl_deprecated_everywhere::BorrowedMutFields) { }
+
+// Code generated for the struct itself uses the accessors without warnings.
+#[self_referencing(
+    accessor_attrs(deprecated),
+    on_drop = log_drop,
+    derive(PartialEq, Eq, Hash, PartialOrd, Ord)
+)]
+struct DeprecatedEverywhere {
+    #[compare]
+    text: Box<str>,
+    #[borrows(text)]
+    #[covariant]
+    #[delegate(Iterator<Item = char>)]
+    chars: std::str::Chars<'this>,
+}
+
+fn main() {
+    let instance = DeprecatedAccessor::new(Box::new(1), |data| data);
+    assert_eq!(**instance.borrow_dref(), 1);
+    let mut everywhere = DeprecatedEverywhereBuilder {
+        text: Box::from("ab"),
+        chars_builder: |text| text.chars(),
+    }
+    .build();
+    assert_eq!(everywhere.next(), Some('a'));
+    let (_heads, ()) = everywhere.into_heads_and(|_| ());
+}
@@ -0,0 +1,12 @@
+use ouroboros::self_referencing;
+
+#[self_referencing]
+struct Test {
+    data: Box<String>,
+    #[borrows(data)]
+    #[covariant]
+    #[deref]
+    chars: std::str::Chars<'this>,
+}
+
+fn main() { }
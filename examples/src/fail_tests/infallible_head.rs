@@ -0,0 +1,11 @@
+use ouroboros::self_referencing;
+
+#[self_referencing]
+struct InfallibleHead {
+    #[infallible]
+    data: Box<i32>,
+    #[borrows(data)]
+    dref: &'this i32,
+}
+
+fn main() { }
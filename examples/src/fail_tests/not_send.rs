@@ -0,0 +1,13 @@
+use ouroboros::self_referencing;
+
+#[self_referencing(not_send)]
+struct NotSend {
+    data: Box<i32>,
+    #[borrows(data)]
+    dref: &'this i32,
+}
+
+fn main() {
+    let instance = NotSend::new(Box::new(1), |data| data);
+    std::thread::spawn(move || drop(instance));
+}
@@ -0,0 +1,15 @@
+use ouroboros::self_referencing;
+
+#[self_referencing(not_sync)]
+struct NotSync {
+    data: Box<i32>,
+    #[borrows(data)]
+    dref: &'this i32,
+}
+
+fn main() {
+    let instance = NotSync::new(Box::new(1), |data| data);
+    std::thread::scope(|scope| {
+        scope.spawn(|| drop(instance.borrow_dref()));
+    });
+}
@@ -0,0 +1,15 @@
+use ouroboros::self_referencing;
+
+#[self_referencing]
+struct PeekEscapes {
+    data: Box<i32>,
+    count: i32,
+    #[borrows(data)]
+    #[peeks(count)]
+    #[covariant]
+    refs: (&'this i32, &'this i32),
+}
+
+fn main() {
+    let _ = PeekEscapes::new(Box::new(1), 2, |data, count| (data, count));
+}
@@ -0,0 +1,13 @@
+use ouroboros::self_referencing;
+
+#[self_referencing]
+struct PeekMutablyBorrowed {
+    data: Box<i32>,
+    #[borrows(mut data)]
+    dref: &'this mut i32,
+    #[borrows(dref)]
+    #[peeks(data)]
+    copied: i32,
+}
+
+fn main() { }
@@ -0,0 +1,11 @@
+use ouroboros::self_referencing;
+
+#[self_referencing(per_field_errors)]
+struct Trimmed {
+    text: Box<str>,
+    #[borrows(text)]
+    #[infallible]
+    trimmed: &'this str,
+}
+
+fn main() {}
@@ -0,0 +1,16 @@
+mod inner {
+    use ouroboros::self_referencing;
+
+    #[self_referencing(pub_extras, vis(with_mut = private))]
+    pub struct PrivateWithMut {
+        data: Box<i32>,
+        #[borrows(data)]
+        dref: &'this i32,
+    }
+}
+
+fn main() {
+    let mut instance = inner::PrivateWithMut::new(Box::new(1), |data| data);
+    instance.with(|fields| assert_eq!(**fields.dref, 1));
+    instance.with_mut(|fields| assert_eq!(**fields.dref, 1));
+}
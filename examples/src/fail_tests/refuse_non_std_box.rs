@@ -26,5 +26,5 @@ struct Simple {
 }
 
 fn main() {
-    let simple = Simple::new(Box::new(format!("Hello world")), |data_ref| data_ref);
+    let _simple = Simple::new(Box::new(format!("Hello world")), |data_ref| data_ref);
 }
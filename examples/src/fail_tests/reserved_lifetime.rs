@@ -0,0 +1,39 @@
+use ouroboros::self_referencing;
+
+#[self_referencing(lifetime = 'outer_borrow)]
+struct OuterBorrow {
+    data: Box<i32>,
+    #[borrows(data)]
+    dref: &'outer_borrow i32,
+}
+
+#[self_referencing(lifetime = 'static)]
+struct Static {
+    data: Box<i32>,
+    #[borrows(data)]
+    dref: &'static i32,
+}
+
+#[self_referencing(lifetime = '_)]
+struct Anonymous {
+    data: Box<i32>,
+    #[borrows(data)]
+    dref: &'_ i32,
+}
+
+#[self_referencing(lifetime = 'context_)]
+struct Context {
+    data: Box<i32>,
+    #[borrows(data)]
+    dref: &'context_ i32,
+}
+
+#[self_referencing(lifetime = 'a)]
+struct Parameter<'a> {
+    label: &'a str,
+    data: Box<i32>,
+    #[borrows(data)]
+    dref: &'a i32,
+}
+
+fn main() { }
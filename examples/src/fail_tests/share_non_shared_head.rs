@@ -0,0 +1,11 @@
+use ouroboros::self_referencing;
+
+#[self_referencing]
+struct ShareBox {
+    data: Box<String>,
+    #[borrows(data)]
+    #[share]
+    view: &'this str,
+}
+
+fn main() { }
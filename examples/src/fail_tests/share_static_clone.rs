@@ -0,0 +1,25 @@
+use ouroboros::self_referencing;
+use std::sync::Arc;
+
+struct Cached<'a>(&'a str);
+
+// Only cloning a Cached<'static> is allowed, which #[share] must not rely on.
+impl Clone for Cached<'static> {
+    fn clone(&self) -> Self {
+        Cached(self.0)
+    }
+}
+
+#[self_referencing]
+struct Shared {
+    text: Arc<str>,
+    #[borrows(text)]
+    #[covariant]
+    #[share]
+    cached: Cached<'this>,
+}
+
+fn main() {
+    let shared = Shared::new(Arc::from("text"), |text| Cached(text));
+    let _view = shared.share_cached();
+}
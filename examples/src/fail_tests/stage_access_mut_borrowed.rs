@@ -0,0 +1,13 @@
+use ouroboros::self_referencing;
+
+#[self_referencing]
+struct BoxAndMutRef {
+    data: Box<i32>,
+    #[borrows(mut data)]
+    dref: &'this mut i32,
+}
+
+fn main() {
+    let stage = BoxAndMutRef::start(Box::new(12)).dref(|data| data);
+    stage.with_data(|data| println!("{}", data));
+}
@@ -0,0 +1,12 @@
+use ouroboros::self_referencing;
+
+#[self_referencing]
+struct ThisWithoutBorrows {
+    data: Box<i32>,
+    #[borrows(data)]
+    dref: &'this i32,
+    missing: Option<&'this i32>,
+    also_missing: Vec<&'this str>,
+}
+
+fn main() { }
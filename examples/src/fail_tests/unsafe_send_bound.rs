@@ -0,0 +1,22 @@
+use ouroboros::self_referencing;
+use std::rc::Rc;
+
+/// A read-only pointer into data owned by the struct it is stored in.
+struct RawCursor<'a, T>(*const T, std::marker::PhantomData<&'a T>);
+
+#[self_referencing(unsafe_send)]
+struct UnsafeSend<T: 'static> {
+    data: Box<T>,
+    #[borrows(data)]
+    #[covariant]
+    cursor: RawCursor<'this, T>,
+}
+
+fn main() {
+    let instance = UnsafeSendBuilder {
+        data: Box::new(Rc::new(1)),
+        cursor_builder: |data| RawCursor(data, std::marker::PhantomData),
+    }
+    .build();
+    std::thread::spawn(move || drop(instance));
+}
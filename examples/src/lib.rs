@@ -6,6 +6,7 @@ use std::sync::Arc;
 mod ok_tests;
 
 #[self_referencing]
+#[allow(dead_code)]
 /// A simple struct which contains a `Box<i32>` and a `&'this i32`.
 pub struct BoxAndRef {
     data: Box<i32>,
@@ -13,11 +14,11 @@ pub struct BoxAndRef {
     data_ref: &'this i32,
 }
 
-#[self_referencing(chain_hack)]
-#[allow(clippy::redundant_allocation)]
-/// A chain of references, where c references b which references a. This is an example of a struct
-/// which requires using [chain_hack](https://docs.rs/ouroboros/latest/ouroboros/attr.self_referencing.html#using-chain_hack)
-/// as of the time this was written.
+#[self_referencing]
+#[allow(clippy::redundant_allocation, dead_code)]
+/// A chain of references, where c references b which references a. This used to require
+/// `chain_hack`, but [chained borrows](https://docs.rs/ouroboros/latest/ouroboros/attr.self_referencing.html#chained-borrows)
+/// are now detected automatically.
 pub struct ChainHack {
     a: Box<i32>,
     #[borrows(a)]
@@ -27,17 +28,23 @@ pub struct ChainHack {
 }
 
 #[self_referencing]
+#[allow(dead_code)]
 /// The example provided in the documentation.
 pub struct DocumentationExample {
+    /// The integer which `int_reference` points to.
     int_data: Box<i32>,
+    /// The float which `float_reference` points to.
     float_data: Box<f32>,
+    /// A reference to `int_data`.
     #[borrows(int_data)]
     int_reference: &'this i32,
+    /// A mutable reference to `float_data`.
     #[borrows(mut float_data)]
     float_reference: &'this mut f32,
 }
 
 #[self_referencing(no_doc)]
+#[allow(dead_code)]
 /// This struct is created using `#[self_referencing(no_doc)]` so the generated methods and
 /// builders are hidden from documentation.
 pub struct Undocumented {
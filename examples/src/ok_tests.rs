@@ -1,4 +1,8 @@
+use std::cell::RefCell;
 use std::fmt::Debug;
+use std::marker::PhantomData;
+use std::rc::Rc;
+use std::sync::Arc;
 use ouroboros::self_referencing;
 
 // All tests here should compile and run correctly and pass Miri's safety checks.
@@ -26,6 +30,7 @@ struct BoxAndMutRef {
 }
 
 #[self_referencing(chain_hack, no_doc)]
+#[allow(clippy::redundant_allocation)]
 struct ChainedAndUndocumented {
     data: Box<i32>,
     #[borrows(data)]
@@ -34,6 +39,309 @@ struct ChainedAndUndocumented {
     ref2: &'this &'this i32,
 }
 
+#[self_referencing]
+#[allow(clippy::redundant_allocation)]
+struct AutoChained {
+    a: Box<i32>,
+    #[borrows(a)]
+    b: Box<&'this i32>,
+    #[borrows(b)]
+    c: &'this &'this i32,
+}
+
+#[self_referencing]
+#[allow(clippy::redundant_allocation)]
+struct AutoChainedShared {
+    a: Box<i32>,
+    #[borrows(a)]
+    b: std::sync::Arc<&'this i32>,
+    #[borrows(b)]
+    c: std::rc::Rc<&'this i32>,
+}
+
+fn record_total(fields: ouroboros_impl_drop_hook::BorrowedMutFields) {
+    fields.log.borrow_mut().push(**fields.total);
+}
+
+#[self_referencing(on_drop = record_total)]
+struct DropHook {
+    log: Rc<RefCell<Vec<i32>>>,
+    data: Box<i32>,
+    #[borrows(data)]
+    total: &'this i32,
+}
+
+#[self_referencing]
+struct DerefToTail {
+    data: Box<str>,
+    #[borrows(data)]
+    #[deref]
+    slice: &'this str,
+}
+
+#[self_referencing]
+struct DerefToHead {
+    #[deref]
+    data: Box<str>,
+    #[borrows(data)]
+    slice: &'this str,
+}
+
+#[self_referencing]
+struct DelegateIterator {
+    text: Box<str>,
+    #[borrows(text)]
+    #[covariant]
+    #[delegate(Iterator<Item = char>, DoubleEndedIterator)]
+    chars: std::str::Chars<'this>,
+}
+
+#[self_referencing]
+struct DelegateIo {
+    input: Box<[u8]>,
+    output: Box<[u8; 5]>,
+    #[borrows(input)]
+    #[delegate(std::io::Read)]
+    reader: &'this [u8],
+    #[borrows(mut output)]
+    #[delegate(std::io::Write)]
+    writer: &'this mut [u8],
+}
+
+#[self_referencing]
+struct SharedDocument {
+    text: Arc<String>,
+    numbers: Rc<Vec<i32>>,
+    #[borrows(text)]
+    #[covariant]
+    #[share]
+    words: Vec<&'this str>,
+    #[borrows(numbers)]
+    #[not_covariant]
+    #[share]
+    first: std::cell::Cell<&'this i32>,
+}
+
+#[self_referencing(per_field_errors)]
+struct ParsedNumber {
+    text: Box<str>,
+    #[borrows(text)]
+    trimmed: &'this str,
+    #[borrows(text)]
+    value: i32,
+}
+
+#[self_referencing]
+struct ContextField {
+    context: Box<str>,
+    #[borrows(context)]
+    prefixed: String,
+}
+
+#[self_referencing]
+struct MixedFallibility {
+    text: Box<str>,
+    #[borrows(text)]
+    #[infallible]
+    trimmed: &'this str,
+    #[borrows(text)]
+    value: i32,
+}
+
+#[self_referencing]
+struct DefaultedFields {
+    #[default = Box::new(10)]
+    start: Box<i32>,
+    #[default]
+    label: Box<str>,
+    #[borrows(start)]
+    #[default = |start| start]
+    current: &'this i32,
+    #[borrows(label)]
+    #[default]
+    #[covariant]
+    words: Vec<&'this str>,
+}
+
+#[self_referencing]
+struct PartialDefaults {
+    text: Box<str>,
+    #[default]
+    count: usize,
+    #[borrows(text)]
+    #[covariant]
+    words: Vec<&'this str>,
+}
+
+#[self_referencing]
+struct Staged {
+    tracker: Rc<i32>,
+    text: Box<str>,
+    data: Box<i32>,
+    #[borrows(text)]
+    #[covariant]
+    words: Vec<&'this str>,
+    #[borrows(mut data)]
+    dref: &'this mut i32,
+}
+
+#[self_referencing]
+struct PeekedSeparator {
+    text: Box<str>,
+    separator: char,
+    #[borrows(text)]
+    #[peeks(separator)]
+    #[covariant]
+    parts: Box<[&'this str]>,
+    #[borrows(parts)]
+    #[peeks(separator, text)]
+    joined: String,
+}
+
+#[self_referencing]
+struct Conditional {
+    data: Box<i32>,
+    #[cfg(any())]
+    missing: DoesNotExist,
+    #[cfg(test)]
+    present: Box<str>,
+    #[borrows(data)]
+    dref: &'this i32,
+    #[cfg(all(any(), test))]
+    #[borrows(data, missing)]
+    missing_ref: &'this DoesNotExist,
+    #[cfg(all(test, not(any())))]
+    #[borrows(present)]
+    #[default]
+    present_ref: &'this str,
+}
+
+#[self_referencing(
+    builder_attrs(must_use),
+    accessor_attrs(inline),
+    heads_attrs(derive(Debug, PartialEq))
+)]
+struct PassThroughAttrs {
+    data: Box<i32>,
+    #[borrows(data)]
+    #[accessor_attrs(deprecated(note = "use borrow_data instead"))]
+    dref: &'this i32,
+}
+
+#[self_referencing(heads_derive(Debug, Clone, PartialEq), fields_derive(Debug))]
+struct DerivedHeads {
+    name: String,
+    data: Box<i32>,
+    #[borrows(data)]
+    dref: &'this i32,
+}
+
+#[self_referencing(derive(PartialEq, Eq, Hash, PartialOrd, Ord))]
+struct ComparedText {
+    text: String,
+    #[borrows(text)]
+    first_word: &'this str,
+}
+
+#[self_referencing(derive(PartialEq, Eq, Hash))]
+struct ComparedByName {
+    #[compare]
+    name: String,
+    data: Box<i32>,
+    #[borrows(data)]
+    #[not_covariant]
+    dref: &'this i32,
+}
+
+#[self_referencing(assert_send, assert_sync)]
+struct SendAndSync<T: 'static> {
+    data: Box<T>,
+    #[borrows(data)]
+    dref: &'this T,
+}
+
+/// A read-only pointer into data owned by the struct it is stored in.
+struct RawCursor<'a>(*const u8, PhantomData<&'a [u8]>);
+
+#[self_referencing(unsafe_send, not_sync)]
+struct UnsafeSend {
+    data: Box<[u8]>,
+    #[borrows(data)]
+    #[covariant]
+    cursor: RawCursor<'this>,
+}
+
+mod item_visibility {
+    use ouroboros::self_referencing;
+
+    #[self_referencing(vis(builder = pub, new = private, into_heads = pub(crate), heads = pub))]
+    pub struct Visible {
+        data: Box<i32>,
+        #[borrows(data)]
+        dref: &'this i32,
+    }
+
+    pub fn make_visible(data: i32) -> Visible {
+        Visible::new(Box::new(data), |data| data)
+    }
+
+    #[self_referencing]
+    pub struct SharedLines {
+        text: std::sync::Arc<String>,
+        #[borrows(text)]
+        #[covariant]
+        #[share]
+        pub(crate) lines: Vec<&'this str>,
+    }
+
+    pub fn make_shared_lines(text: &str) -> SharedLines {
+        SharedLines::new(std::sync::Arc::new(text.to_owned()), |text| text.lines().collect())
+    }
+
+    #[self_referencing(per_field_errors, vis(new = pub(crate), heads = pub(crate)))]
+    pub struct VisibleErrors {
+        text: Box<str>,
+        #[borrows(text)]
+        value: i32,
+    }
+}
+
+#[self_referencing(lifetime = 'own)]
+struct CustomLifetime<'this> {
+    label: &'this str,
+    this: String,
+    #[borrows(this)]
+    dref: &'own str,
+    #[borrows(this)]
+    #[covariant]
+    words: Vec<&'own str>,
+}
+
+struct SliceStream<'a>(&'a [i32]);
+
+impl<'a> futures_core::Stream for SliceStream<'a> {
+    type Item = i32;
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<i32>> {
+        let next = self.0.first().copied();
+        if next.is_some() {
+            self.0 = &self.0[1..];
+        }
+        std::task::Poll::Ready(next)
+    }
+}
+
+#[self_referencing]
+struct DelegateStream {
+    items: Vec<i32>,
+    #[borrows(items)]
+    #[covariant]
+    #[delegate(futures_core::Stream<Item = i32>)]
+    stream: SliceStream<'this>,
+}
+
 /// This test just makes sure that the macro copes with a ton of template parameters being thrown at
 /// it, specifically checking that the templates work fine even when a generated struct doesn't need
 /// all of them. (E.G. heads will only contain 'd, A, and B.)
@@ -153,6 +461,23 @@ fn try_new_recover_heads() {
     }
 }
 
+#[test]
+fn chained_borrows() {
+    let chained = AutoChainedBuilder {
+        a: Box::new(12),
+        b_builder: |a: &i32| Box::new(a),
+        c_builder: |b: &&i32| b,
+    }
+    .build();
+    assert_eq!(***chained.borrow_c(), 12);
+    let shared = AutoChainedShared::new(
+        Box::new(34),
+        |a| std::sync::Arc::new(a),
+        |b| std::rc::Rc::new(*b),
+    );
+    assert_eq!(***shared.borrow_c(), 34);
+}
+
 #[test]
 fn into_heads() {
     let bar = BoxAndRefBuilder {
@@ -163,6 +488,65 @@ fn into_heads() {
     assert!(bar.into_heads().data == Box::new(12));
 }
 
+#[test]
+fn on_drop() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let hooked = DropHook::new(log.clone(), Box::new(12), |data| data);
+    drop(hooked);
+    assert_eq!(*log.borrow(), vec![12]);
+    let hooked = DropHook::new(log.clone(), Box::new(34), |data| data);
+    let heads = hooked.into_heads();
+    assert_eq!(*heads.data, 34);
+    assert_eq!(*log.borrow(), vec![12, 34]);
+}
+
+#[test]
+fn deref_field() {
+    let tail = DerefToTail::new(Box::from("Hello World"), |data| &data[6..]);
+    assert_eq!(&*tail, "World");
+    assert_eq!(tail.len(), 5);
+    let head = DerefToHead::new(Box::from("Hello World"), |data| &data[6..]);
+    assert_eq!(&**head, "Hello World");
+}
+
+#[test]
+fn delegate_iterator() {
+    let chars = DelegateIterator::new(Box::from("abc"), |text| text.chars());
+    assert_eq!(chars.rev().collect::<String>(), "cba");
+}
+
+#[test]
+fn delegate_io() {
+    use std::io::{Read, Write};
+    let mut io = DelegateIo::new(
+        Box::from(&b"hello"[..]),
+        Box::new([0; 5]),
+        |input| input,
+        |output| &mut output[..],
+    );
+    let mut buf = [0; 5];
+    io.read_exact(&mut buf).unwrap();
+    io.write_all(&buf).unwrap();
+    io.flush().unwrap();
+    assert_eq!(io.into_heads().output, Box::new(*b"hello"));
+}
+
+// Miri crashes with Pin<Box<Future>> types due to
+// https://github.com/rust-lang/miri/issues/1038
+#[cfg(not(feature = "miri"))]
+#[tokio::test]
+async fn delegate_stream() {
+    use futures_core::Stream;
+    let mut stream = DelegateStream::new(vec![1, 2], |items| SliceStream(items));
+    let mut collected = Vec::new();
+    while let Some(item) =
+        std::future::poll_fn(|cx| std::pin::Pin::new(&mut stream).poll_next(cx)).await
+    {
+        collected.push(item);
+    }
+    assert_eq!(collected, vec![1, 2]);
+}
+
 #[test]
 fn box_and_mut_ref() {
     let mut bar = BoxAndMutRefBuilder {
@@ -212,6 +596,116 @@ fn self_reference_with() {
     assert_eq!(**bar.borrow_dref(), 123);
 }
 
+#[test]
+fn per_field_errors() {
+    type Error = ParsedNumberBuildError<std::io::Error, std::num::ParseIntError>;
+    fn parse(text: &str) -> Result<ParsedNumber, Error> {
+        ParsedNumberTryBuilder {
+            text: Box::from(text),
+            trimmed_builder: |text| match text.trim() {
+                "" => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "empty")),
+                trimmed => Ok(trimmed),
+            },
+            value_builder: |text| text.trim().parse(),
+        }
+        .try_build()
+    }
+    let parsed = parse(" 12 ").unwrap();
+    assert_eq!(*parsed.borrow_trimmed(), "12");
+    assert_eq!(*parsed.borrow_value(), 12);
+    let error = parse("  ").err().unwrap();
+    assert!(matches!(error, ParsedNumberBuildError::Trimmed(..)));
+    assert_eq!(error.to_string(), "failed to build `trimmed`: empty");
+    let error = parse("abc").err().unwrap();
+    assert!(std::error::Error::source(&error).is_some());
+    if let ParsedNumberBuildError::Value(_, heads) = error {
+        assert_eq!(&*heads.text, "abc");
+    } else {
+        panic!("Test failed.");
+    }
+}
+
+#[test]
+fn infallible_builder() {
+    let mixed = MixedFallibilityTryBuilder {
+        text: Box::from(" 12 "),
+        trimmed_builder: |text| text.trim(),
+        value_builder: |text| text.trim().parse(),
+    }
+    .try_build()
+    .unwrap();
+    assert_eq!(*mixed.borrow_trimmed(), "12");
+    let result = MixedFallibility::try_new_or_recover(
+        Box::from("abc"),
+        |text| text.trim(),
+        |text| text.parse::<i32>(),
+    );
+    assert!(matches!(result, Err((_, heads)) if &*heads.text == "abc"));
+}
+
+#[test]
+fn from_heads() {
+    let bar = BoxAndRef::new(Box::new(12), |data| data);
+    let bar = BoxAndRef::from_heads(bar.into_heads(), |data| data);
+    let bar = BoxAndRefHeadsBuilder {
+        heads: bar.into_heads(),
+        dref_builder: |data| data,
+    }
+    .build();
+    assert_eq!(*bar.borrow_dref(), &12);
+}
+
+#[test]
+fn try_from_heads_retry() {
+    let mut heads = BoxAndRef::new(Box::new(12), |data| data).into_heads();
+    let mut attempts = 0;
+    let bar = loop {
+        attempts += 1;
+        let result = BoxAndRefTryHeadsBuilder {
+            heads,
+            dref_builder: |data| if attempts < 3 { Err(attempts) } else { Ok(data) },
+        }
+        .try_build_or_recover();
+        match result {
+            Ok(bar) => break bar,
+            Err((_, recovered)) => heads = recovered,
+        }
+    };
+    assert_eq!(attempts, 3);
+    assert_eq!(*bar.borrow_dref(), &12);
+    let result = BoxAndRef::try_from_heads(bar.into_heads(), |_data| Err(56));
+    assert!(matches!(result, Err(56)));
+}
+
+// Miri crashes with Pin<Box<Future>> types due to
+// https://github.com/rust-lang/miri/issues/1038
+#[cfg(not(feature = "miri"))]
+#[tokio::test]
+async fn async_from_heads() {
+    let bar = BoxAndRef::new(Box::new(12), |data| data);
+    let bar = BoxAndRef::from_heads_async(bar.into_heads(), |data| Box::pin(async move { data }))
+        .await;
+    let bar = BoxAndRefAsyncTryHeadsBuilder {
+        heads: bar.into_heads(),
+        dref_builder: |data| Box::pin(async move { Result::<_, ()>::Ok(data) }),
+    }
+    .try_build()
+    .await
+    .unwrap();
+    assert_eq!(*bar.borrow_dref(), &12);
+}
+
+#[test]
+fn into_heads_and() {
+    let bar = BoxAndMutRef::new(Box::new(12), |data| data);
+    let (heads, doubled) = bar.into_heads_and(|fields| {
+        **fields.dref += 1;
+        **fields.dref * 2
+    });
+    assert_eq!(*heads.data, 13);
+    assert_eq!(doubled, 26);
+}
+
 #[test]
 fn single_lifetime() {
     #[self_referencing]
@@ -274,3 +768,375 @@ mod test_hygiene {
         dref: &'this i32,
     }
 }
+
+#[test]
+fn owning_iter() {
+    fn doubled(items: &[i32]) -> std::iter::Map<std::slice::Iter<'_, i32>, fn(&i32) -> i32> {
+        items.iter().map(|item| item * 2)
+    }
+    let mut iter = ouroboros::OwningIter::from_owner(vec![1, 2, 3], doubled);
+    assert_eq!(iter.len(), 3);
+    assert_eq!(iter.next_back(), Some(6));
+    assert_eq!(iter.next(), Some(2));
+    assert_eq!(iter.owner(), &[1, 2, 3]);
+    assert_eq!(iter.into_owner(), vec![1, 2, 3]);
+}
+
+#[test]
+fn owning_iter_from_closure() {
+    let separator = ',';
+    let mut iter = ouroboros::OwningIter::new(String::from("a,bc"), |text| {
+        Box::new(text.split(separator).map(str::len))
+    });
+    assert_eq!(iter.next(), Some(1));
+    assert_eq!(iter.next(), Some(2));
+    assert_eq!(iter.next(), None);
+    assert_eq!(iter.into_owner(), "a,bc");
+}
+
+#[test]
+fn owning_iter_iter_family() {
+    struct Skipped;
+    impl<'a> ouroboros::IterFamily<'a, [i32]> for Skipped {
+        type Iter = std::iter::Copied<std::iter::Skip<std::slice::Iter<'a, i32>>>;
+    }
+    let skip = 1;
+    let mut iter = ouroboros::OwningIter::from_closure::<Skipped>(vec![1, 2, 3], |items| {
+        items.iter().skip(skip).copied()
+    });
+    assert_eq!(iter.len(), 2);
+    assert_eq!(iter.next_back(), Some(3));
+    assert_eq!(iter.next(), Some(2));
+    assert_eq!(iter.next(), None);
+}
+
+#[test]
+fn yoke() {
+    use ouroboros::Yoke;
+    use std::borrow::Cow;
+    let yoke: Yoke<Box<str>, Cow<'static, str>> =
+        Yoke::attach_to_cart(Box::from("text"), |text| Cow::Borrowed(&text[1..]));
+    assert_eq!(&**yoke.get(), "ext");
+    let failed: Result<Yoke<_, &'static str>, _> =
+        yoke.try_map_project(|text, _| match text {
+            Cow::Borrowed(text) => text.strip_prefix('x').ok_or(()),
+            Cow::Owned(_) => Err(()),
+        });
+    assert!(failed.is_err());
+    let parsed: Result<Yoke<Rc<String>, Vec<&'static str>>, ()> =
+        Yoke::try_attach_to_cart(Rc::new(String::from("a,b")), |text| {
+            Ok(text.split(',').collect())
+        });
+    let first: Yoke<_, &'static str> = parsed.unwrap().map_project(|words, _| words[0]);
+    assert_eq!(*first.get(), "a");
+    assert_eq!(*first.into_backing_cart(), "a,b");
+}
+
+#[test]
+fn share_field() {
+    let document = SharedDocument::new(
+        Arc::new(String::from("a b c")),
+        Rc::new(vec![1, 2]),
+        |text| text.split(' ').collect(),
+        |numbers| std::cell::Cell::new(&numbers[0]),
+    );
+    let words = document.share_words();
+    let first = document.share_first();
+    drop(document);
+    assert_eq!(first.with_first(|first| *first.get()), 1);
+    assert_eq!(Rc::strong_count(first.borrow_numbers()), 1);
+    let cloned = words.clone();
+    assert_eq!(Arc::strong_count(words.borrow_text()), 2);
+    let joined = std::thread::spawn(move || cloned.borrow_words().join(""))
+        .join()
+        .unwrap();
+    assert_eq!(joined, "abc");
+}
+
+#[test]
+fn context_builders() {
+    let calls = RefCell::new(0);
+    let bar = BoxAndRef::new_with_context(&calls, Box::new(12), |calls, data| {
+        *calls.borrow_mut() += 1;
+        data
+    });
+    assert_eq!(*bar.borrow_dref(), &12);
+    let bar = BoxAndRefBuilderWithContext {
+        context_: &calls,
+        data: bar.into_heads().data,
+        dref_builder: |calls, data| {
+            *calls.borrow_mut() += 1;
+            data
+        },
+    }
+    .build();
+    assert_eq!(*bar.borrow_dref(), &12);
+    assert_eq!(*calls.borrow(), 2);
+
+    let radix = 16;
+    let mixed = MixedFallibilityTryBuilderWithContext {
+        context_: &radix,
+        text: Box::from(" ff "),
+        trimmed_builder: |_, text| text.trim(),
+        value_builder: |radix, text| i32::from_str_radix(text.trim(), *radix),
+    }
+    .try_build()
+    .unwrap();
+    assert_eq!(*mixed.borrow_value(), 255);
+    let result = MixedFallibility::try_new_or_recover_with_context(
+        &radix,
+        Box::from("xyz"),
+        |_, text| text.trim(),
+        |radix, text| i32::from_str_radix(text, *radix),
+    );
+    assert!(matches!(result, Err((_, heads)) if &*heads.text == "xyz"));
+
+    let result = ParsedNumber::try_new_with_context(
+        "",
+        Box::from("12"),
+        |prefix, text| text.strip_prefix(prefix).ok_or(()),
+        |_, text| text.parse::<i32>(),
+    );
+    assert_eq!(*result.unwrap().borrow_value(), 12);
+
+    let field = ContextFieldBuilderWithContext {
+        context_: "> ",
+        context: Box::from("a"),
+        prefixed_builder: |prefix, context| format!("{}{}", prefix, context),
+    }
+    .build();
+    assert_eq!(field.borrow_prefixed(), "> a");
+}
+
+#[test]
+fn default_fields() {
+    let defaulted = DefaultedFields::default();
+    assert_eq!(**defaulted.borrow_current(), 10);
+    assert!(defaulted.borrow_words().is_empty());
+    let partial = DefaultedFieldsDefaultBuilder {}.build();
+    assert_eq!(**partial.borrow_current(), 10);
+    let full = DefaultedFieldsBuilder {
+        start: Box::new(1),
+        label: Box::from("a b"),
+        current_builder: |start| start,
+        words_builder: |label| label.split(' ').collect(),
+    }
+    .build();
+    assert_eq!(full.borrow_words(), &["a", "b"]);
+    let partial = PartialDefaultsDefaultBuilder {
+        text: Box::from("a b"),
+        words_builder: |text| text.split(' ').collect(),
+    }
+    .build();
+    assert_eq!(partial.borrow_words().len(), 2);
+    assert_eq!(*partial.borrow_count(), 0);
+}
+
+#[test]
+fn stage_builder() {
+    let tracker = Rc::new(0);
+    let stage = Staged::start(tracker.clone())
+        .text(Box::from("a b"))
+        .data(Box::new(1));
+    assert_eq!(Rc::strong_count(stage.borrow_tracker()), 2);
+    let stage = stage.words(|text| text.split(' ').collect());
+    assert_eq!(stage.borrow_words(), &["a", "b"]);
+    assert_eq!(stage.with_data(|data| **data), 1);
+    let staged = stage
+        .dref(|data| {
+            *data += 1;
+            data
+        })
+        .finish();
+    assert_eq!(staged.with_dref(|dref| **dref), 2);
+    drop(staged);
+    assert_eq!(Rc::strong_count(&tracker), 1);
+    let partial = Staged::start(tracker.clone()).text(Box::from("a b"));
+    assert_eq!(Rc::strong_count(&tracker), 2);
+    drop(partial);
+    assert_eq!(Rc::strong_count(&tracker), 1);
+}
+
+#[test]
+fn peek_fields() {
+    let peeked = PeekedSeparatorBuilder {
+        text: Box::from("a,b"),
+        separator: ',',
+        parts_builder: |text, separator| text.split(*separator).collect(),
+        joined_builder: |parts, separator, text| {
+            assert_eq!(&**text, "a,b");
+            parts.join(&separator.to_string())
+        },
+    }
+    .build();
+    assert_eq!(&**peeked.borrow_parts(), &["a", "b"]);
+    assert_eq!(peeked.borrow_joined(), "a,b");
+    let result = PeekedSeparator::try_new(
+        Box::from("a;b"),
+        ';',
+        |text, separator| Ok(text.split(*separator).collect()),
+        |parts, _, _| match parts.len() {
+            2 => Err(()),
+            _ => Ok(String::new()),
+        },
+    );
+    assert!(result.is_err());
+    let peeked = PeekedSeparator::start(Box::from("a b"))
+        .separator(' ')
+        .parts(|text, separator| text.split(*separator).collect())
+        .joined(|parts, separator, _| parts.join(&separator.to_string()))
+        .finish();
+    assert_eq!(peeked.borrow_joined(), "a b");
+}
+
+#[test]
+fn conditional_fields() {
+    let conditional = ConditionalBuilder {
+        data: Box::new(1),
+        #[cfg(any())]
+        missing: DoesNotExist,
+        present: Box::from("present"),
+        dref_builder: |data| data,
+        #[cfg(any())]
+        missing_ref_builder: |_, missing| missing,
+        present_ref_builder: |present| present,
+    }
+    .build();
+    assert_eq!(**conditional.borrow_dref(), 1);
+    assert_eq!(*conditional.borrow_present_ref(), "present");
+    conditional.with(|fields| {
+        assert_eq!(*fields.data, 1);
+        assert_eq!(fields.present, "present");
+    });
+    let heads = conditional.into_heads();
+    assert_eq!(&*heads.present, "present");
+    let conditional = Conditional::from_heads(heads, |data| data, |present| present);
+    assert_eq!(**conditional.borrow_dref(), 1);
+    let conditional =
+        Conditional::new_with_context(&2, Box::new(1), Box::from("a"), |_, data| data, |_, a| a);
+    assert_eq!(*conditional.borrow_present_ref(), "a");
+    let result: Result<Conditional, (i32, _)> = Conditional::try_new_or_recover(
+        Box::new(3),
+        Box::from("b"),
+        |data| Ok(data),
+        |_| Err(4),
+    );
+    let (error, heads) = result.err().unwrap();
+    assert_eq!((error, *heads.data, &*heads.present), (4, 3, "b"));
+    let defaulted = ConditionalDefaultBuilder {
+        data: Box::new(5),
+        present: Box::from("c"),
+        dref_builder: |data| data,
+    }
+    .build();
+    assert_eq!(*defaulted.borrow_present_ref(), "");
+}
+
+#[test]
+#[allow(deprecated)]
+fn pass_through_attrs() {
+    let instance = PassThroughAttrsBuilder {
+        data: Box::new(1),
+        dref_builder: |data| data,
+    }
+    .build();
+    assert_eq!(**instance.borrow_dref(), 1);
+    assert_eq!(format!("{:?}", instance.into_heads()), "Heads { data: 1 }");
+}
+
+#[test]
+fn derived_heads_and_fields() {
+    let instance = DerivedHeads::new("a".to_owned(), Box::new(1), |data| data);
+    assert_eq!(
+        instance.with(|fields| format!("{:?}", fields)),
+        "BorrowedFields { dref: 1, data: 1, name: \"a\" }"
+    );
+    let result: Result<DerivedHeads, (i32, _)> =
+        DerivedHeads::try_new_or_recover("b".to_owned(), Box::new(2), |_| Err(3));
+    let (error, heads) = result.err().unwrap();
+    assert_eq!(error, 3);
+    assert_eq!(heads.clone(), heads);
+    assert_eq!(format!("{:?}", heads), "Heads { data: 2, name: \"b\" }");
+}
+
+#[test]
+fn compared_fields() {
+    use std::collections::{BTreeMap, HashMap};
+    let text = |text: &str| {
+        ComparedText::new(text.to_owned(), |text| text.split(' ').next().unwrap())
+    };
+    let mut ordered = BTreeMap::new();
+    ordered.insert(text("b c"), 1);
+    ordered.insert(text("a b"), 2);
+    ordered.insert(text("b a"), 3);
+    let keys: Vec<_> = ordered.keys().map(|key| key.borrow_text().as_str()).collect();
+    assert_eq!(keys, ["a b", "b a", "b c"]);
+    assert!(text("a b") == text("a b"));
+
+    let by_name = |name: &str, data: i32| {
+        ComparedByName::new(name.to_owned(), Box::new(data), |data| data)
+    };
+    let mut cache = HashMap::new();
+    cache.insert(by_name("a", 1), "first");
+    cache.insert(by_name("b", 2), "second");
+    assert_eq!(cache.get(&by_name("a", 3)), Some(&"first"));
+    assert!(by_name("a", 1) != by_name("b", 1));
+}
+
+#[test]
+fn auto_traits() {
+    let instance = SendAndSync::new(Box::new(1), |data| data);
+    let handle = std::thread::spawn(move || **instance.borrow_dref());
+    assert_eq!(handle.join().unwrap(), 1);
+    let instance = UnsafeSendBuilder {
+        data: Box::from(&[1, 2][..]),
+        cursor_builder: |data| RawCursor(data.as_ptr(), PhantomData),
+    }
+    .build();
+    let handle = std::thread::spawn(move || instance.with_cursor(|cursor| unsafe { *cursor.0 }));
+    assert_eq!(handle.join().unwrap(), 1);
+}
+
+#[test]
+fn item_visibility() {
+    use item_visibility::{make_visible, VisibleBuilder};
+    let instance = VisibleBuilder {
+        data: Box::new(1),
+        dref_builder: |data| data,
+    }
+    .build();
+    assert_eq!(*instance.into_heads().data, 1);
+    assert_eq!(*make_visible(2).into_heads().data, 2);
+}
+
+#[test]
+fn item_visibility_share() {
+    use item_visibility::{make_shared_lines, SharedLinesLinesView};
+    let view: SharedLinesLinesView = make_shared_lines("a\nb").share_lines();
+    assert_eq!(view.borrow_lines(), &["a", "b"]);
+}
+
+#[test]
+fn item_visibility_build_error() {
+    use item_visibility::{VisibleErrors, VisibleErrorsBuildError};
+    let result = VisibleErrors::try_new(Box::from("abc"), |text| text.parse::<i32>());
+    match result {
+        Err(VisibleErrorsBuildError::Value(_, heads)) => assert_eq!(&*heads.text, "abc"),
+        _ => panic!("Test failed."),
+    }
+}
+
+#[test]
+fn custom_lifetime() {
+    let text = String::from("label");
+    let instance = CustomLifetime::new(
+        &text,
+        "a b".to_owned(),
+        |this| this,
+        |this| this.split(' ').collect(),
+    );
+    assert_eq!(*instance.borrow_label(), "label");
+    assert_eq!(instance.borrow_words(), &["a", "b"]);
+    instance.with(|fields| assert_eq!(*fields.dref, "a b"));
+    assert_eq!(instance.into_heads().this, "a b");
+}
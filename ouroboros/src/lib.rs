@@ -2,6 +2,10 @@
 //!
 //! See the documentation of [`ouroboros_examples`](https://docs.rs/ouroboros_examples) for
 //! sample documentation of structs which have had the macro applied to them.
+//!
+//! For the common case of owning a collection and lazily iterating over it, the crate also
+//! provides a ready-made [`OwningIter`] type built with the macro. [`Yoke`] covers the similar
+//! case of a single owner with a single value borrowing from it.
 
 #![allow(clippy::needless_doctest_main)]
 
@@ -69,10 +73,10 @@
 /// - Normal borrowing rules apply, E.G. a field cannot be borrowed mutably twice.
 /// - Fields that are borrowed must be of a data type that implement
 ///   [`StableDeref`](https://docs.rs/stable_deref_trait/1.2.0/stable_deref_trait/trait.StableDeref.html).
-///   Normally this just means `Box<T>`.
+///   Normally this just means `Box<T>`. Builders can be given temporary access to fields of other
+///   types, see [Peeking at earlier fields](#peeking-at-earlier-fields).
 /// - Fields that use the `'this` lifetime must have a corresponding `#[borrows()]` annotation.
-///   The error for this needs some work, currently you will get an error saying that `'this` is
-///   undefined at the location it was illegally used in.
+/// - Each field can only have one `#[borrows()]` annotation.
 ///
 /// Violating them will result in an error message directly pointing out the violated rule.
 ///
@@ -189,18 +193,501 @@
 ///
 /// These annotations control whether or not a `borrow_*` method is generated for that field.
 ///
-/// # Using `chain_hack`
-/// Unfortunately, as of September 2020, Rust has a
-/// [known limitation in its type checker](https://users.rust-lang.org/t/why-does-this-not-compile-box-t-target-t/49027/7?u=aaaaa)
-/// which prevents chained references from working (I.E. structs where field C references field B
-/// which references field A.) To counteract this problem, you can use
-/// `#[self_referencing(chain_hack)]` to allow creating these kinds of structs at the cost of
-/// additional restrictions and possible loss of clarity in some error messages. The main limitation
-/// is that all fields that are borrowed must be of type `Box<T>`. A nice error message will be
-/// generated if you use a different type. There should be no other limitations, but some
-/// configurations may produce strange compiler errors. If you find such a configuration, please
-/// open an issue on the [Github repository](https://github.com/joshua-maros/ouroboros/issues).
-/// You can view a documented example of a struct which uses `chain_hack` [here](https://docs.rs/ouroboros_examples/latest/ouroboros_examples/struct.ChainHack.html).
+/// # Naming the `'this` lifetime
+/// If `'this` is already taken, for example by a lifetime parameter of the struct,
+/// `#[self_referencing(lifetime = 'own)]` makes fields borrow from other fields using `'own`
+/// instead. Every other lifetime, including `'this`, is then left untouched. The generated
+/// functions and structs use the chosen name as well, so for example `with` takes a closure
+/// generic over `'own`. `'static`, `'_`, `'outer_borrow` and `'context_` cannot be chosen, since
+/// they are built into Rust or used by the generated functions, and neither can a lifetime
+/// parameter of the struct.
+/// ```rust
+/// use ouroboros::self_referencing;
+///
+/// #[self_referencing(lifetime = 'own)]
+/// struct Labeled<'this> {
+///     label: &'this str,
+///     text: String,
+///     #[borrows(text)]
+///     first_word: &'own str,
+/// }
+///
+/// fn main() {
+///     let labeled = Labeled::new("label", "a b".to_owned(), |text| {
+///         text.split(' ').next().unwrap()
+///     });
+///     assert_eq!(*labeled.borrow_first_word(), "a");
+///     assert_eq!(*labeled.borrow_label(), "label");
+/// }
+/// ```
+///
+/// # Deref
+/// Placing `#[deref]` on a field makes the struct implement
+/// [`Deref`](https://doc.rust-lang.org/std/ops/trait.Deref.html) with that field as its target,
+/// which is convenient for wrappers that mostly exist to expose a single field. Only one field can
+/// be marked this way and it cannot be a field that is mutably borrowed by another field.
+/// Because `Deref::Target` is an associated type, it cannot refer to the lifetime of the borrow of
+/// `self`. This means the field's type must either not use `'this` at all, in which case `Target`
+/// is the field's type, or it must be a reference like `&'this T`, in which case `Target` is `T`.
+/// Other types that use `'this`, such as `Ast<'this>`, produce an error. For those, mark the field
+/// `#[covariant]` and use the generated `borrow_*` method instead.
+/// ```rust
+/// use ouroboros::self_referencing;
+///
+/// #[self_referencing]
+/// struct Line {
+///     text: Box<String>,
+///     #[borrows(text)]
+///     #[deref]
+///     trimmed: &'this str,
+/// }
+///
+/// fn main() {
+///     let line = Line::new(Box::new("  hello  ".to_owned()), |text| text.trim());
+///     assert_eq!(line.len(), 5);
+/// }
+/// ```
+///
+/// # Delegating traits
+/// `#[delegate(Trait1, Trait2, ...)]` on a **tail field** makes the struct implement those traits
+/// by forwarding every call to the field through `with_FIELD` and `with_FIELD_mut`. The supported
+/// traits are `Iterator`, `DoubleEndedIterator`, `ExactSizeIterator`, `std::io::Read`,
+/// `std::io::Write`, and `Stream` from `futures`. Traits are written as paths that must resolve
+/// where the struct is declared, for example `#[delegate(Iterator, std::io::Read, futures::Stream)]`.
+/// Delegating `Stream` requires the struct and the field to be `Unpin`.
+///
+/// The iterator traits can be written with or without their full path, while `Read`, `Write` and
+/// `Stream` must be written with theirs, like `std::io::Read` or `futures::Stream`. Any other path
+/// is an error.
+///
+/// The items produced by a delegated `Iterator` or `Stream` cannot borrow from `'this`, since they
+/// could outlive the struct. When the type of the field mentions `'this`, the macro can't tell
+/// whether the items do too, so the `Item` type must be written out like
+/// `#[delegate(Iterator<Item = char>)]`. For example, `std::str::Chars<'this>` can be delegated
+/// because its items are `char`s, but `std::str::Split<'this, char>` cannot because it yields
+/// `&'this str`. Writing an `Item` type which mentions `'this` is a compile error.
+/// ```rust
+/// use ouroboros::self_referencing;
+///
+/// #[self_referencing]
+/// struct OwnedChars {
+///     text: Box<str>,
+///     #[borrows(text)]
+///     #[covariant]
+///     #[delegate(Iterator<Item = char>, DoubleEndedIterator)]
+///     chars: std::str::Chars<'this>,
+/// }
+///
+/// fn main() {
+///     let chars = OwnedChars::new(Box::from("abc"), |text| text.chars());
+///     assert_eq!(chars.rev().collect::<String>(), "cba");
+/// }
+/// ```
+///
+/// # Comparing and hashing
+/// `#[self_referencing(derive(PartialEq, Eq, Hash, PartialOrd, Ord))]` implements the listed traits
+/// by comparing or hashing the fields of two structs one after the other, in the order they are
+/// declared, just like the built-in derives. The fields are read through their `borrow_FIELD`
+/// methods, so only fields which have one can be compared. When some fields can't be compared, or
+/// shouldn't be, mark the ones that should with `#[compare]` and the others will be ignored. Like
+/// the built-in derives, every type parameter of the struct must implement the trait.
+/// ```rust
+/// use ouroboros::self_referencing;
+/// use std::collections::HashMap;
+///
+/// #[self_referencing(derive(PartialEq, Eq, Hash))]
+/// struct CacheKey {
+///     #[compare]
+///     path: String,
+///     contents: Box<str>,
+///     #[borrows(contents)]
+///     #[not_covariant]
+///     lines: Vec<&'this str>,
+/// }
+///
+/// fn main() {
+///     let key = |path: &str| {
+///         CacheKey::new(path.to_owned(), Box::from("a\nb"), |contents| contents.lines().collect())
+///     };
+///     let mut cache = HashMap::new();
+///     cache.insert(key("a.txt"), 2);
+///     assert_eq!(cache.get(&key("a.txt")), Some(&2));
+/// }
+/// ```
+///
+/// # Thread safety
+/// The struct implements `Send` and `Sync` automatically when every field would, with the fields
+/// which borrow from others stored as if `'this` was `'static`. This is usually what you want, but
+/// it is easy to lose one of them without noticing, for example by borrowing from an `Rc`. The
+/// following options make the intent explicit:
+/// - `assert_send` and `assert_sync` fail to compile if the struct does not implement `Send` or
+///   `Sync`. For generic structs, this is checked under the assumption that every type parameter
+///   is both `Send` and `Sync`.
+/// - `not_send` and `not_sync` stop the struct from implementing `Send` or `Sync`, for example to
+///   keep the freedom of adding fields which are not thread safe later.
+/// - `unsafe_send` and `unsafe_sync` implement `Send` or `Sync` for the struct even though its
+///   fields don't, which is useful for fields containing raw pointers. For generic structs, the
+///   implementation requires every type parameter to implement the same trait.
+///
+/// Only one of the three options can be used for each trait.
+///
+/// ### Safety
+/// `unsafe_send` and `unsafe_sync` are `unsafe impl`s in disguise. With `unsafe_send`, the struct
+/// must be safe to move to another thread along with everything it owns, taking into account that
+/// the borrowing fields keep pointing into the heads after the move. With `unsafe_sync`, it must
+/// be safe to use every method which takes `&self`, including `with_FIELD` and `borrow_FIELD`,
+/// from several threads at once. Heads like `Rc` which can be cloned through a shared reference
+/// usually make this impossible.
+/// ```rust
+/// use ouroboros::self_referencing;
+/// use std::rc::Rc;
+///
+/// #[self_referencing(assert_send, not_sync)]
+/// struct Tokens {
+///     text: String,
+///     #[borrows(text)]
+///     #[covariant]
+///     tokens: Vec<&'this str>,
+/// }
+///
+/// #[self_referencing(not_send)]
+/// struct Shared {
+///     text: Rc<str>,
+///     #[borrows(text)]
+///     first_line: &'this str,
+/// }
+///
+/// fn main() {
+///     let tokens = Tokens::new("a b".to_owned(), |text| text.split(' ').collect());
+///     let handle = std::thread::spawn(move || tokens.borrow_tokens().len());
+///     assert_eq!(handle.join().unwrap(), 2);
+/// }
+/// ```
+///
+/// # Sharing fields
+/// When the heads a field borrows from are `Arc<T>` or `Rc<T>`, cloning them does not move the data
+/// they point to, so a clone of the field remains valid for as long as those clones exist. Marking
+/// such a field `#[share]` generates `share_FIELD(&self)`, which returns a `MyStructFieldView`
+/// bundling a clone of the field with clones of every head it borrows from. Views are independent
+/// of the original struct and of each other, so with `Arc` heads they can be sent to other threads.
+/// The field must implement `Clone` for every lifetime `'this` could be, and may only immutably
+/// borrow head fields of type `Arc` or `Rc`. Views provide `with_FIELD`, `borrow_FIELD` if the
+/// field is covariant, and `borrow_HEAD` for each of the shared heads. `share_FIELD`, the view
+/// struct and its methods all have the visibility of the field.
+/// ```rust
+/// use ouroboros::self_referencing;
+/// use std::sync::Arc;
+///
+/// #[self_referencing]
+/// struct Document {
+///     text: Arc<String>,
+///     #[borrows(text)]
+///     #[covariant]
+///     #[share]
+///     lines: Vec<&'this str>,
+/// }
+///
+/// fn main() {
+///     let document = Document::new(Arc::new("a\nb".to_owned()), |text| text.lines().collect());
+///     let view = document.share_lines();
+///     drop(document);
+///     let count = std::thread::spawn(move || view.borrow_lines().len()).join().unwrap();
+///     assert_eq!(count, 2);
+/// }
+/// ```
+///
+/// # Chained borrows
+/// Structs where field C borrows field B which borrows field A are supported without any extra
+/// annotations. Rust's type checker has historically had a
+/// [known limitation](https://users.rust-lang.org/t/why-does-this-not-compile-box-t-target-t/49027/7?u=aaaaa)
+/// with these chains, so when a borrowed field is itself self-referencing and is a `Box<T>`,
+/// `Arc<T>`, or `Rc<T>`, the macro names `T` directly in the builder signatures instead of going
+/// through `Deref`. Borrowing any other kind of self-referencing field is a compile error. Earlier
+/// versions required `#[self_referencing(chain_hack)]` to enable this behavior. The option is
+/// still accepted but no longer does anything. You can view a documented
+/// example of a chained struct [here](https://docs.rs/ouroboros_examples/latest/ouroboros_examples/struct.ChainHack.html).
+///
+/// # Running code before the struct is dropped
+/// Since the fields of the generated struct are private, you cannot write a `Drop` implementation
+/// which accesses them yourself. Instead, use `#[self_referencing(on_drop = path::to::function)]`.
+/// The generated `Drop` implementation will call the function with a `BorrowedMutFields` (the same
+/// value that `with_mut` provides) before any fields are dropped. This is useful for things like
+/// flushing a writer which borrows one of the heads:
+/// ```rust
+/// use ouroboros::self_referencing;
+/// use std::io::Write;
+///
+/// fn flush(fields: ouroboros_impl_buffered_file::BorrowedMutFields) {
+///     fields.writer.flush().unwrap();
+/// }
+///
+/// #[self_referencing(on_drop = flush)]
+/// struct BufferedFile {
+///     file: Box<std::fs::File>,
+///     #[borrows(mut file)]
+///     #[not_covariant]
+///     writer: std::io::BufWriter<&'this mut std::fs::File>,
+/// }
+/// # fn main() { }
+/// ```
+/// `BorrowedMutFields` is declared in the module containing the generated code, which is named
+/// `ouroboros_impl_` followed by the name of your struct in snake case. The same function is also
+/// called by `into_heads()` before the self-referencing fields are dropped.
+///
+/// # Per-field errors
+/// By default, every builder passed to `try_new()` must fail with the same error type. With
+/// `#[self_referencing(per_field_errors)]`, each fallible builder gets its own error type instead
+/// and the try constructors return a generated `MyStructBuildError` enum. It has one variant per
+/// **self-referencing field** that uses a builder, named after the field in `UpperCamelCase`. Each
+/// variant holds the error returned by that builder followed by the `Heads` of the struct, so the
+/// `_or_recover` constructors are not generated in this mode. The enum implements `Debug`,
+/// `Display` and `Error` when all of the error types do, and has an `into_heads()` method. Using
+/// `per_field_errors` when every builder is marked `#[infallible]` is an error, since the enum
+/// would have no variants.
+/// ```rust
+/// use ouroboros::self_referencing;
+///
+/// #[self_referencing(per_field_errors)]
+/// struct Config {
+///     text: Box<str>,
+///     #[borrows(text)]
+///     name: &'this str,
+///     #[borrows(text)]
+///     port: u16,
+/// }
+///
+/// fn main() {
+///     let result = ConfigTryBuilder {
+///         text: Box::from("localhost:http"),
+///         name_builder: |text| text.split(':').next().ok_or("missing name"),
+///         port_builder: |text| text.rsplit(':').next().unwrap().parse(),
+///     }
+///     .try_build();
+///     match result {
+///         Err(ConfigBuildError::Port(error, heads)) => {
+///             println!("{} is not a valid port: {}", &*heads.text, error)
+///         }
+///         _ => unreachable!(),
+///     }
+/// }
+/// ```
+///
+/// # Passing context to builders
+/// Builders can only borrow fields of the struct. When several builders need something that is not
+/// stored in the struct, such as a configuration or an allocator, use `new_with_context()` or
+/// `MyStructBuilderWithContext` instead of capturing it in every closure. Each builder is given a
+/// reference to the context as its first argument, followed by the fields it borrows. The context
+/// can be any type and is only borrowed for the duration of the constructor. It is passed as
+/// `context_`, so that it does not clash with a field named `context`.
+/// ```rust
+/// use ouroboros::self_referencing;
+///
+/// #[self_referencing]
+/// struct Record {
+///     line: Box<str>,
+///     #[borrows(line)]
+///     #[covariant]
+///     fields: Vec<&'this str>,
+///     #[borrows(line)]
+///     #[covariant]
+///     comment: Option<&'this str>,
+/// }
+///
+/// fn main() {
+///     let separator = ';';
+///     let record = RecordBuilderWithContext {
+///         context_: &separator,
+///         line: Box::from("a;b # note"),
+///         fields_builder: |separator, line| line.split(*separator).collect(),
+///         comment_builder: |_, line| line.split_once(" # ").map(|(_, comment)| comment),
+///     }
+///     .build();
+///     assert_eq!(record.borrow_fields(), &["a", "b # note"]);
+///     assert_eq!(record.borrow_comment(), &Some("note"));
+/// }
+/// ```
+///
+/// # Default values
+/// Fields can be given a default value with `#[default]`, which uses `Default::default()`, or
+/// `#[default = expr]`. For **self-referencing fields** which use a builder, `expr` should be the
+/// builder function instead, and `#[default]` creates a builder which ignores its arguments and
+/// returns `Default::default()`. When any field has a default, a `MyStructDefaultBuilder` is
+/// generated which omits every field that has one. If every field has a default, `MyStruct` also
+/// implements `Default`.
+/// ```rust
+/// use ouroboros::self_referencing;
+///
+/// #[self_referencing]
+/// struct Parser {
+///     #[default = Box::from("1 2 3")]
+///     input: Box<str>,
+///     #[default]
+///     position: usize,
+///     #[borrows(input)]
+///     #[covariant]
+///     #[default = |input| input.split(' ').collect()]
+///     tokens: Vec<&'this str>,
+/// }
+///
+/// fn main() {
+///     let parser = Parser::default();
+///     assert_eq!(parser.borrow_tokens().len(), 3);
+///     let parser = ParserDefaultBuilder {}.build();
+///     assert_eq!(*parser.borrow_position(), 0);
+/// }
+/// ```
+///
+/// # Peeking at earlier fields
+/// Only fields that implement `StableDeref` can be borrowed, since the struct may be moved after it
+/// is constructed. `#[peeks(a, b)]` gives a builder temporary access to earlier fields of any
+/// type, such as a configuration value passed to the constructor. The builder receives `&A`,
+/// `&B` after the fields it borrows. These references only last for the duration of the call, so
+/// the value the builder returns cannot contain them. Fields which are mutably borrowed by another
+/// field cannot be peeked at, and `#[peeks()]` can only be used alongside `#[borrows()]`.
+/// ```rust
+/// use ouroboros::self_referencing;
+///
+/// #[self_referencing]
+/// struct Csv {
+///     text: Box<str>,
+///     separator: char,
+///     #[borrows(text)]
+///     #[peeks(separator)]
+///     #[covariant]
+///     cells: Vec<&'this str>,
+/// }
+///
+/// fn main() {
+///     let csv = Csv::new(Box::from("a;b"), ';', |text, separator| {
+///         text.split(*separator).collect()
+///     });
+///     assert_eq!(csv.borrow_cells(), &["a", "b"]);
+/// }
+/// ```
+///
+/// # Step-by-step construction
+/// When a struct is assembled across several functions, `MyStruct::start(first_field)` begins a
+/// step-by-step builder instead. It returns a `MyStructStage1`, whose only method is named after
+/// the second field and takes the same argument as that field in `new()`. That method returns a
+/// `MyStructStage2`, and so on. The last stage has a `finish()` method which returns the
+/// constructed struct. Every stage provides `with_FIELD` for the fields which have already been
+/// initialized, along with `borrow_FIELD` for head fields and covariant fields, except for fields
+/// which have been mutably borrowed. Dropping a stage drops the fields it contains. Since the
+/// stages are numbered by field, they are not generated for structs with conditionally compiled
+/// fields.
+/// ```rust
+/// use ouroboros::self_referencing;
+///
+/// #[self_referencing]
+/// struct Sentence {
+///     text: Box<str>,
+///     #[borrows(text)]
+///     #[covariant]
+///     words: Vec<&'this str>,
+///     #[borrows(text)]
+///     #[covariant]
+///     longest: &'this str,
+/// }
+///
+/// fn split(stage: SentenceStage1) -> SentenceStage2 {
+///     stage.words(|text| text.split(' ').collect())
+/// }
+///
+/// fn main() {
+///     let stage = split(Sentence::start(Box::from("a bc d")));
+///     assert_eq!(stage.borrow_words().len(), 3);
+///     let sentence = stage
+///         .longest(|text| text.split(' ').max_by_key(|word| word.len()).unwrap())
+///         .finish();
+///     assert_eq!(*sentence.borrow_longest(), "bc");
+/// }
+/// ```
+///
+/// # Conditional compilation
+/// Fields can be marked with `#[cfg(...)]`. The attribute is copied to every generated item which
+/// mentions the field, such as its constructor argument, builder field, `with_FIELD` and
+/// `borrow_FIELD` methods, and its field in `Heads` and `BorrowedFields`. When the field is
+/// compiled out, code which creates the struct must leave it out as well, which can be done by
+/// putting the same `#[cfg(...)]` on the argument or builder field. A field which borrows or
+/// peeks at a conditionally compiled field must only be compiled when that field is, for example
+/// by having the same `#[cfg(...)]` attributes.
+/// ```rust
+/// use ouroboros::self_referencing;
+///
+/// #[self_referencing]
+/// struct Document {
+///     text: String,
+///     #[cfg(debug_assertions)]
+///     comments: String,
+///     #[borrows(text)]
+///     title: &'this str,
+///     #[cfg(debug_assertions)]
+///     #[borrows(comments)]
+///     first_comment: &'this str,
+/// }
+///
+/// fn main() {
+///     let document = DocumentBuilder {
+///         text: "Title\nBody".to_owned(),
+///         #[cfg(debug_assertions)]
+///         comments: "First".to_owned(),
+///         title_builder: |text| text.lines().next().unwrap(),
+///         #[cfg(debug_assertions)]
+///         first_comment_builder: |comments| comments,
+///     }
+///     .build();
+///     assert_eq!(*document.borrow_title(), "Title");
+/// }
+/// ```
+/// `#[cfg_attr(...)]` is left on the field as it is, so it cannot be used to add attributes like
+/// `#[borrows()]` which the macro handles itself. Structs with conditionally compiled fields do not
+/// get the [step-by-step builder](#step-by-step-construction), and `per_field_errors` cannot be
+/// used when such a field has a fallible builder.
+///
+/// # Attributes on generated items
+/// Attributes can be added to generated items through options of the macro:
+/// - `builder_attrs(...)` adds them to every builder struct, including `MyStructBuilder`,
+///   `MyStructTryBuilder` and the stages of the step-by-step builder.
+/// - `accessor_attrs(...)` adds them to every `with_FIELD`, `borrow_FIELD` and `with_FIELD_mut`
+///   function, as well as `with` and `with_mut`.
+/// - `heads_attrs(...)` adds them to the `Heads` struct.
+/// - `heads_derive(...)` derives the listed traits for the `Heads` struct, and
+///   `fields_derive(...)` derives them for `BorrowedFields` and `BorrowedMutFields`. For example,
+///   `heads_derive(Debug, PartialEq)` makes it possible to compare the heads returned by
+///   `try_new_or_recover`. `BorrowedMutFields` holds mutable references, so traits like `Clone`
+///   can't be derived for it.
+///
+/// A field can also be marked with `#[accessor_attrs(...)]` to add attributes to only the
+/// accessors of that field. Attributes are added as they are written, so they must be valid on
+/// the kind of item they are added to. For example, `inline` can be used in `accessor_attrs` but
+/// not in `builder_attrs`.
+/// ```rust
+/// use ouroboros::self_referencing;
+///
+/// #[self_referencing(builder_attrs(must_use), accessor_attrs(inline))]
+/// struct Parsed {
+///     text: String,
+///     #[borrows(text)]
+///     #[accessor_attrs(deprecated(note = "use borrow_text instead"))]
+///     first_word: &'this str,
+/// }
+///
+/// fn main() {
+///     let parsed = Parsed::new("a b".to_owned(), |text| text.split(' ').next().unwrap());
+///     assert_eq!(parsed.borrow_text(), "a b");
+/// }
+/// ```
+///
+/// # Debugging the generated code
+/// When code generated by the macro fails to compile, `#[self_referencing(debug_expand)]` writes
+/// everything the macro generated for that struct to a file, formatted with `rustfmt` if it is
+/// installed. Setting the `OUROBOROS_DEBUG_EXPAND` environment variable to `1` while building does
+/// the same for every struct. The file is placed in an `ouroboros_expanded` folder in `OUT_DIR`
+/// if the crate has a build script and in the target directory otherwise, and a warning pointing
+/// at it is shown where the struct is declared. The file is named after the crate and the struct,
+/// and structs with the same name in different modules get a number added in the order they are
+/// expanded. Cargo does not know that the macro reads the environment variable, so crates which
+/// were already built have to be touched or cleaned for it to take effect.
 ///
 /// # What does the macro generate?
 /// The `#[self_referencing]` struct will replace your definition with an unsafe self-referencing
@@ -208,7 +695,9 @@
 /// struct definition. Documentation is generated for all items, so building documentation for
 /// your project allows accessing detailed information about available functions. Using
 /// `#[self_referencing(no_doc)]` will hide the generated items from documentation if it is becoming
-/// too cluttered.
+/// too cluttered. Doc comments written on a field are copied to the items generated for it, such
+/// as its `with_FIELD` and `borrow_FIELD` methods, its builder fields and its field in `Heads`,
+/// ahead of the generated documentation. They are not copied when `no_doc` is used.
 ///
 /// ### A quick note on visibility
 /// The visibility of generated items is dependent on one of two things. If the
@@ -219,6 +708,37 @@
 /// `new()` and `with()`.) You can use `#[self_referencing(pub_extras)]` to make these items have the
 /// same visibility as the struct itself.
 ///
+/// For finer control, `vis(...)` sets the visibility of specific kinds of items, taking precedence
+/// over `pub_extras`. Visibilities are written as they would be on an item declared next to the
+/// struct, and `private` makes items visible only to the module the struct is declared in:
+/// - `builder`: every builder struct, its fields and its build functions.
+/// - `new`: the constructors, like `new()`, `try_new()`, `from_heads()`, `new_with_context()` and
+///   `start()`, and the `MyStructBuildError` enum returned by the try constructors.
+/// - `with` and `with_mut`: `with()` and `with_mut()`, along with the structs they provide.
+/// - `into_heads`: `into_heads()` and `into_heads_and()`.
+/// - `heads`: the `Heads` struct and its fields.
+/// ```rust
+/// mod parser {
+///     use ouroboros::self_referencing;
+///
+///     #[self_referencing(vis(builder = pub, into_heads = pub, heads = pub, with_mut = private))]
+///     pub struct Parsed {
+///         text: String,
+///         #[borrows(text)]
+///         first_word: &'this str,
+///     }
+/// }
+///
+/// fn main() {
+///     let parsed = parser::ParsedBuilder {
+///         text: "a b".to_owned(),
+///         first_word_builder: |text| text.split(' ').next().unwrap(),
+///     }
+///     .build();
+///     assert_eq!(parsed.into_heads().text, "a b");
+/// }
+/// ```
+///
 /// # List of generated items
 /// ### `MyStruct::new(fields...) -> MyStruct`
 /// A basic constructor. It accepts values for each field in the order you declared them in. For
@@ -251,7 +771,10 @@
 /// Similar to the regular `new()` function, except the functions wich create values for all
 /// **self-referencing fields** can return `Result<>`s. If any of those are `Err`s, that error will be
 /// returned instead of an instance of `MyStruct`. The preferred way to use this function is through
-/// `MyStructTryBuilder` and its `try_build()` function.
+/// `MyStructTryBuilder` and its `try_build()` function. Builders for fields marked
+/// `#[infallible]` return the field's value directly, just like in `new()`, so that only the
+/// builders which can actually fail need to return a `Result`. If every builder is infallible, the
+/// error type cannot be inferred and must be specified explicitly.
 /// ### `MyStruct::try_new_async<E>(fields...) -> Result<MyStruct, E>`
 /// Similar to the regular `new_async()` function, except the functions wich create values for all
 /// **self-referencing fields** can return `Result<>`s. If any of those are `Err`s, that error will be
@@ -261,6 +784,35 @@
 /// Similar to the `try_new_async()` function, except that all the **head fields** are returned along side
 /// the original error in case of an error. The preferred way to use this function is through
 /// `MyStructAsyncTryBuilder` and its `try_build_or_recover()` function.
+/// ### `MyStruct::from_heads(heads: Heads, builders...) -> MyStruct`
+/// Like `new()`, except that all **head fields** are taken from a `Heads` value instead of being
+/// passed individually. Only the arguments for **self-referencing fields** are passed after it.
+/// This is the counterpart to `into_heads()` and `try_new_or_recover()`, which makes it easy to
+/// retry construction after an error. `from_heads_async`, `try_from_heads`,
+/// `try_from_heads_or_recover`, `try_from_heads_async` and `try_from_heads_or_recover_async` are
+/// generated alongside it and mirror the other constructors.
+/// ### `MyStructHeadsBuilder`
+/// The builder version of `from_heads()`. It has a `heads` field followed by the fields of
+/// `MyStructBuilder` for **self-referencing fields**. `MyStructAsyncHeadsBuilder`,
+/// `MyStructTryHeadsBuilder` and `MyStructAsyncTryHeadsBuilder` correspond to the other builders.
+/// ### `MyStruct::new_with_context<C>(context_: &C, fields...) -> MyStruct`
+/// Like `new()`, except that every builder also receives `context_` as its first argument.
+/// `try_new_with_context` and `try_new_or_recover_with_context` are generated alongside it. There
+/// are no async versions. See [Passing context to builders](#passing-context-to-builders).
+/// ### `MyStructBuilderWithContext`
+/// The builder version of `new_with_context()`. It has a `context_` field followed by the fields
+/// of `MyStructBuilder`. `MyStructTryBuilderWithContext` is the builder version of
+/// `try_new_with_context()`.
+/// ### `MyStruct::start(first_field) -> MyStructStage1`
+/// Starts a step-by-step builder. `MyStructStage1` through `MyStructStageN`, where N is the number
+/// of fields, are generated alongside it. Not generated when a field is conditionally compiled.
+/// See [Step-by-step construction](#step-by-step-construction).
+/// ### `MyStructDefaultBuilder`
+/// Only generated when a field is marked `#[default]`, see [Default values](#default-values).
+/// ### `impl Default for MyStruct`
+/// Only generated when every field is marked `#[default]`, see [Default values](#default-values).
+/// ### `MyStructBuildError`
+/// Only generated when `per_field_errors` is used, see [Per-field errors](#per-field-errors).
 /// ### `MyStruct::with_FIELD<R>(&self, user: FnOnce(field: &FieldType) -> R) -> R`
 /// This function is generated for every **tail and immutably-borrowed field** in your struct. It
 /// allows safely accessing
@@ -285,8 +837,36 @@
 /// fields as mutable at the same time and also have immutable access to any remaining fields.
 /// ### `MyStruct::into_heads(self) -> Heads`
 /// Drops all self-referencing fields and returns a struct containing all **head fields**.
+/// ### `MyStruct::into_heads_and<R>(self, user: FnOnce(fields: AllFields) -> R) -> (Heads, R)`
+/// Calls `user` with the same fields that `with_mut` provides, then behaves like `into_heads`. This
+/// allows computing an owned value from the self-referencing fields right before they are
+/// dropped, for example summarizing a parsed document without parsing it again. `R` cannot
+/// contain `'this`, since the fields it would borrow from no longer exist once the function
+/// returns.
+/// ### `impl Deref for MyStruct`
+/// Only generated when a field is marked `#[deref]`, see [Deref](#deref).
+/// ### Delegated traits
+/// Generated for every trait listed in a `#[delegate()]` annotation, see
+/// [Delegating traits](#delegating-traits).
+/// ### Compared and hashed traits
+/// Generated for every trait listed in `derive()`, see
+/// [Comparing and hashing](#comparing-and-hashing).
+/// ### `MyStruct::share_FIELD(&self) -> MyStructFieldView`
+/// Only generated for fields marked `#[share]`, see [Sharing fields](#sharing-fields).
+/// ### `impl Send for MyStruct` and `impl Sync for MyStruct`
+/// Only generated when `unsafe_send` or `unsafe_sync` is used, see
+/// [Thread safety](#thread-safety).
+/// ### `impl Drop for MyStruct`
+/// Only generated when `on_drop` is used, see
+/// [Running code before the struct is dropped](#running-code-before-the-struct-is-dropped).
 pub use ouroboros_macro::self_referencing;
 
+extern crate self as ouroboros;
+mod owning_iter;
+pub use owning_iter::{IterBuilder, IterFamily, OwningIter};
+mod yoke;
+pub use yoke::{Yoke, Yokeable};
+
 #[doc(hidden)]
 pub mod macro_help {
     use stable_deref_trait::StableDeref;
@@ -306,6 +886,23 @@ pub mod macro_help {
     std_type_check!(is_std_arc_type T std::sync::Arc<T>);
     std_type_check!(is_std_rc_type T std::rc::Rc<T>);
 
+    /// Used by assert_send to check that a struct implements Send.
+    pub fn assert_send<T: ?Sized + Send>() { }
+
+    /// Used by assert_sync to check that a struct implements Sync.
+    pub fn assert_sync<T: ?Sized + Sync>() { }
+
+    /// Stored in structs marked not_send to stop them from implementing Send. It does not stop
+    /// them from implementing Sync.
+    pub struct NotSend(std::marker::PhantomData<*const ()>);
+
+    // Sharing a NotSend between threads is harmless since it contains no data.
+    unsafe impl Sync for NotSend { }
+
+    /// Stored in structs marked not_sync to stop them from implementing Sync. It does not stop
+    /// them from implementing Send.
+    pub struct NotSync(std::marker::PhantomData<std::cell::Cell<()>>);
+
     /// Converts a reference to an object implementing Deref to a static reference to the data it
     /// Derefs to. This is obviously unsafe because the compiler can no longer guarantee that the
     /// data outlives the reference. This function is templated to only work for containers that
@@ -0,0 +1,191 @@
+use crate::self_referencing;
+use stable_deref_trait::StableDeref;
+use std::marker::PhantomData;
+use std::ops::Deref;
+
+/// Creates an iterator borrowing from a `&'a T`. This is implemented for every
+/// `FnOnce(&'a T) -> I` where `I` is an iterator, so it does not usually need to be implemented
+/// manually. See [`OwningIter`] for how it is used.
+pub trait IterBuilder<'a, T: ?Sized> {
+    /// The type of item produced by the iterator.
+    type Item;
+    /// The iterator this builder creates.
+    type Iter: Iterator<Item = Self::Item>;
+    /// Creates the iterator.
+    fn build(self, target: &'a T) -> Self::Iter;
+}
+
+impl<'a, T: ?Sized + 'a, F, I> IterBuilder<'a, T> for F
+where
+    F: FnOnce(&'a T) -> I,
+    I: Iterator,
+{
+    type Item = I::Item;
+    type Iter = I;
+
+    fn build(self, target: &'a T) -> I {
+        self(target)
+    }
+}
+
+/// Names the type of an iterator borrowing from a `&'a T`, for every lifetime `'a`. Closures can
+/// only return a type which borrows from their argument when that type is named by a bound, so
+/// [`OwningIter::from_closure`] is given a type implementing this trait, usually an empty struct:
+/// ```rust
+/// use ouroboros::{IterFamily, OwningIter};
+///
+/// struct Chars;
+///
+/// impl<'a> IterFamily<'a, str> for Chars {
+///     type Iter = std::str::Chars<'a>;
+/// }
+///
+/// fn main() {
+///     let iter = OwningIter::from_closure::<Chars>(String::from(" abc "), |text| {
+///         text.trim().chars()
+///     });
+///     assert_eq!(iter.rev().collect::<String>(), "cba");
+/// }
+/// ```
+pub trait IterFamily<'a, T: ?Sized> {
+    /// The iterator created from a `&'a T`.
+    type Iter: Iterator;
+}
+
+/// An iterator which owns the data it is iterating over. It is created from an owner such as a
+/// `Vec<T>`, `String` or `Box<T>` and a builder which creates an iterator borrowing from whatever
+/// the owner derefs to:
+/// ```rust
+/// use ouroboros::OwningIter;
+///
+/// fn squares(numbers: &[i32]) -> impl Iterator<Item = i32> + '_ {
+///     numbers.iter().map(|n| n * n)
+/// }
+///
+/// fn make_iter() -> impl DoubleEndedIterator<Item = char> {
+///     let text = String::from("owned text");
+///     OwningIter::from_owner(text, str::chars)
+/// }
+///
+/// fn main() {
+///     assert_eq!(make_iter().rev().next(), Some('t'));
+///     let iter = OwningIter::from_owner(vec![1, 2, 3], squares);
+///     assert_eq!(iter.owner(), &[1, 2, 3]);
+///     assert_eq!(iter.sum::<i32>(), 14);
+///     let lengths = OwningIter::new(String::from("a bc"), |text| {
+///         Box::new(text.split(' ').map(str::len))
+///     });
+///     assert_eq!(lengths.collect::<Vec<_>>(), [1, 2]);
+/// }
+/// ```
+/// The builder passed to [`OwningIter::from_owner`] must work for references of any lifetime, so
+/// it is usually a function or a method like `str::chars`. Rust does not infer that the iterator
+/// returned by a closure borrows from its argument, so closures are passed to
+/// [`OwningIter::from_closure`] along with an [`IterFamily`] naming the iterator, or to
+/// [`OwningIter::new`], which boxes the iterator they return. Since an iterator cannot hand out
+/// references to itself, the items produced must not borrow from the owner either.
+///
+/// `OwningIter` implements `DoubleEndedIterator` and `ExactSizeIterator` whenever the iterator
+/// created by the builder does.
+pub struct OwningIter<O, F>(OwningIterFields<O, F>)
+where
+    O: StableDeref + 'static,
+    F: for<'a> IterBuilder<'a, <O as Deref>::Target>;
+
+#[self_referencing]
+struct OwningIterFields<O: StableDeref + 'static, F: for<'a> IterBuilder<'a, <O as Deref>::Target>>
+{
+    owner: O,
+    builder: PhantomData<fn(F)>,
+    #[borrows(owner)]
+    #[not_covariant]
+    iter: <F as IterBuilder<'this, <O as Deref>::Target>>::Iter,
+}
+
+impl<O, F, T> OwningIter<O, F>
+where
+    O: StableDeref + 'static,
+    F: for<'a> FnOnce(&'a <O as Deref>::Target) -> Box<dyn Iterator<Item = T> + 'a>,
+{
+    /// Creates an iterator which owns `owner`, using the closure `builder` to create the
+    /// underlying iterator. The iterator is boxed, so unlike with [`OwningIter::from_closure`] it
+    /// does not need to be named, but the result only implements `Iterator`.
+    pub fn new(owner: O, builder: F) -> Self {
+        Self::from_owner(owner, builder)
+    }
+}
+
+impl<O, F> OwningIter<O, F>
+where
+    O: StableDeref + 'static,
+    F: for<'a> IterBuilder<'a, <O as Deref>::Target>,
+{
+    /// Creates an iterator which owns `owner`, using the closure `builder` to create the
+    /// underlying iterator, whose type is named by the [`IterFamily`] `M`.
+    pub fn from_closure<M>(owner: O, builder: F) -> Self
+    where
+        M: for<'a> IterFamily<'a, <O as Deref>::Target>,
+        F: for<'a> FnOnce(
+            &'a <O as Deref>::Target,
+        ) -> <M as IterFamily<'a, <O as Deref>::Target>>::Iter,
+    {
+        Self::from_owner(owner, builder)
+    }
+
+    /// Creates an iterator which owns `owner`, using `builder` to create the underlying iterator.
+    pub fn from_owner(owner: O, builder: F) -> Self {
+        Self(
+            OwningIterFieldsBuilder {
+                owner,
+                builder: PhantomData,
+                iter_builder: |owner| builder.build(owner),
+            }
+            .build(),
+        )
+    }
+
+    /// Returns a reference to the data the iterator is iterating over.
+    pub fn owner(&self) -> &<O as Deref>::Target {
+        self.0.borrow_owner()
+    }
+
+    /// Stops iterating and returns the owner.
+    pub fn into_owner(self) -> O {
+        self.0.into_heads().owner
+    }
+}
+
+impl<O, F, T> Iterator for OwningIter<O, F>
+where
+    O: StableDeref + 'static,
+    F: for<'a> IterBuilder<'a, <O as Deref>::Target, Item = T>,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.0.with_iter_mut(|iter| iter.next())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.with_iter(|iter| iter.size_hint())
+    }
+}
+
+impl<O, F, T> DoubleEndedIterator for OwningIter<O, F>
+where
+    O: StableDeref + 'static,
+    F: for<'a> IterBuilder<'a, <O as Deref>::Target, Item = T>,
+    for<'a> <F as IterBuilder<'a, <O as Deref>::Target>>::Iter: DoubleEndedIterator,
+{
+    fn next_back(&mut self) -> Option<T> {
+        self.0.with_iter_mut(|iter| iter.next_back())
+    }
+}
+
+impl<O, F, T> ExactSizeIterator for OwningIter<O, F>
+where
+    O: StableDeref + 'static,
+    F: for<'a> IterBuilder<'a, <O as Deref>::Target, Item = T>,
+    for<'a> <F as IterBuilder<'a, <O as Deref>::Target>>::Iter: ExactSizeIterator,
+{
+}
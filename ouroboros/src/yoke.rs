@@ -0,0 +1,208 @@
+use crate::macro_help::stable_deref_and_change_lifetime;
+use stable_deref_trait::StableDeref;
+use std::borrow::{Cow, ToOwned};
+use std::marker::PhantomData;
+
+/// A type which can be stored in a [`Yoke`]. `Self` is the type with every borrowed lifetime set
+/// to `'static`, and `Output` is the same type with those lifetimes set to `'a`. For example,
+/// `&'static str` is `Yokeable` with an `Output` of `&'a str`.
+///
+/// # Safety
+///
+/// `Self` and `Output` must be the same type apart from lifetimes, and that type must be
+/// covariant in the lifetimes being replaced. This is what allows [`Yoke::get`] to hand out an
+/// `Output` with a lifetime no longer than the borrow of the yoke.
+pub unsafe trait Yokeable<'a>: 'static {
+    /// This type with its `'static` lifetimes shortened to `'a`.
+    type Output: 'a;
+
+    /// Shortens the lifetimes of a reference to this type. This is usually implemented as
+    /// `{ self }`, which only compiles if the type is covariant.
+    fn transform(&'a self) -> &'a Self::Output;
+
+    /// Shortens the lifetimes of this type. This is usually implemented as `{ self }`, which only
+    /// compiles if the type is covariant.
+    fn transform_owned(self) -> Self::Output;
+
+    /// Lengthens the lifetimes of `from` to `'static`.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the returned value is not used after the data it borrows from
+    /// would become invalid.
+    unsafe fn make(from: Self::Output) -> Self;
+}
+
+unsafe impl<'a, T: ?Sized + 'static> Yokeable<'a> for &'static T {
+    type Output = &'a T;
+
+    fn transform(&'a self) -> &'a &'a T {
+        self
+    }
+
+    fn transform_owned(self) -> &'a T {
+        self
+    }
+
+    unsafe fn make(from: &'a T) -> Self {
+        &*(from as *const T)
+    }
+}
+
+unsafe impl<'a, T: ?Sized + ToOwned + 'static> Yokeable<'a> for Cow<'static, T> {
+    type Output = Cow<'a, T>;
+
+    fn transform(&'a self) -> &'a Cow<'a, T> {
+        self
+    }
+
+    fn transform_owned(self) -> Cow<'a, T> {
+        self
+    }
+
+    unsafe fn make(from: Cow<'a, T>) -> Self {
+        std::mem::transmute(from)
+    }
+}
+
+unsafe impl<'a, Y: for<'b> Yokeable<'b>> Yokeable<'a> for Option<Y> {
+    type Output = Option<<Y as Yokeable<'a>>::Output>;
+
+    fn transform(&'a self) -> &'a Self::Output {
+        // Y and its Output only differ in lifetimes, so Option<Y> and Option<Output> have the
+        // same layout.
+        unsafe { &*(self as *const Self as *const Self::Output) }
+    }
+
+    fn transform_owned(self) -> Self::Output {
+        self.map(Yokeable::transform_owned)
+    }
+
+    unsafe fn make(from: Self::Output) -> Self {
+        from.map(|from| <Y as Yokeable<'a>>::make(from))
+    }
+}
+
+unsafe impl<'a, Y: for<'b> Yokeable<'b>> Yokeable<'a> for Vec<Y> {
+    type Output = Vec<<Y as Yokeable<'a>>::Output>;
+
+    fn transform(&'a self) -> &'a Self::Output {
+        // Y and its Output only differ in lifetimes, so Vec<Y> and Vec<Output> have the same
+        // layout.
+        unsafe { &*(self as *const Self as *const Self::Output) }
+    }
+
+    fn transform_owned(self) -> Self::Output {
+        self.into_iter().map(Yokeable::transform_owned).collect()
+    }
+
+    unsafe fn make(from: Self::Output) -> Self {
+        from.into_iter()
+            .map(|from| <Y as Yokeable<'a>>::make(from))
+            .collect()
+    }
+}
+
+/// Bundles a cart, which owns some data, with a value which borrows from that data. This covers
+/// the simple case of a self-referencing struct with a single owner and a single covariant
+/// borrowing field without having to write a struct definition:
+/// ```rust
+/// use ouroboros::Yoke;
+///
+/// fn main() {
+///     let yoke: Yoke<String, Vec<&'static str>> =
+///         Yoke::attach_to_cart(String::from("a b c"), |text| text.split(' ').collect());
+///     assert_eq!(yoke.get(), &["a", "b", "c"]);
+///     assert_eq!(yoke.backing_cart(), "a b c");
+///     // Projections can narrow the borrowed value down further.
+///     let last: Yoke<String, Option<&'static str>> =
+///         yoke.map_project(|words, _| words.last().copied());
+///     assert_eq!(last.get(), &Some("c"));
+/// }
+/// ```
+/// The type of the borrowed value is written with `'static` in place of the borrowed lifetime,
+/// see [`Yokeable`].
+pub struct Yoke<C, Y: for<'a> Yokeable<'a>> {
+    // Fields are dropped in declaration order, so the yokeable is dropped before the cart it
+    // borrows from.
+    yokeable: Y,
+    cart: C,
+}
+
+impl<C: StableDeref, Y: for<'a> Yokeable<'a>> Yoke<C, Y> {
+    /// Creates a yoke by calling `f` with a reference to the data the cart derefs to.
+    pub fn attach_to_cart<F>(cart: C, f: F) -> Self
+    where
+        F: for<'de> FnOnce(&'de C::Target) -> <Y as Yokeable<'de>>::Output,
+    {
+        let borrowed = f(unsafe { stable_deref_and_change_lifetime(&cart) });
+        Self {
+            yokeable: unsafe { Y::make(borrowed) },
+            cart,
+        }
+    }
+
+    /// Like [`Yoke::attach_to_cart`], but `f` can fail.
+    pub fn try_attach_to_cart<E, F>(cart: C, f: F) -> Result<Self, E>
+    where
+        F: for<'de> FnOnce(&'de C::Target) -> Result<<Y as Yokeable<'de>>::Output, E>,
+    {
+        let borrowed = f(unsafe { stable_deref_and_change_lifetime(&cart) })?;
+        Ok(Self {
+            yokeable: unsafe { Y::make(borrowed) },
+            cart,
+        })
+    }
+}
+
+impl<C, Y: for<'a> Yokeable<'a>> Yoke<C, Y> {
+    /// Returns the borrowed value, with its lifetime shortened to that of the borrow of `self`.
+    pub fn get<'a>(&'a self) -> &'a <Y as Yokeable<'a>>::Output {
+        self.yokeable.transform()
+    }
+
+    /// Returns the cart the value borrows from.
+    pub fn backing_cart(&self) -> &C {
+        &self.cart
+    }
+
+    /// Drops the borrowed value and returns the cart.
+    pub fn into_backing_cart(self) -> C {
+        let Self { yokeable, cart } = self;
+        drop(yokeable);
+        cart
+    }
+
+    /// Replaces the borrowed value with one computed from it. The `PhantomData` argument prevents
+    /// `f` from making assumptions about how long the borrow lasts.
+    pub fn map_project<P, F>(self, f: F) -> Yoke<C, P>
+    where
+        P: for<'a> Yokeable<'a>,
+        F: for<'a> FnOnce(
+            <Y as Yokeable<'a>>::Output,
+            PhantomData<&'a ()>,
+        ) -> <P as Yokeable<'a>>::Output,
+    {
+        let projected = f(self.yokeable.transform_owned(), PhantomData);
+        Yoke {
+            yokeable: unsafe { P::make(projected) },
+            cart: self.cart,
+        }
+    }
+
+    /// Like [`Yoke::map_project`], but `f` can fail. On failure, the cart is dropped.
+    pub fn try_map_project<P, E, F>(self, f: F) -> Result<Yoke<C, P>, E>
+    where
+        P: for<'a> Yokeable<'a>,
+        F: for<'a> FnOnce(
+            <Y as Yokeable<'a>>::Output,
+            PhantomData<&'a ()>,
+        ) -> Result<<P as Yokeable<'a>>::Output, E>,
+    {
+        let projected = f(self.yokeable.transform_owned(), PhantomData)?;
+        Ok(Yoke {
+            yokeable: unsafe { P::make(projected) },
+            cart: self.cart,
+        })
+    }
+}
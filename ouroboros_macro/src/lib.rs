@@ -4,9 +4,11 @@ use proc_macro2::TokenStream as TokenStream2;
 use proc_macro2::{Group, Span, TokenTree};
 use proc_macro_error::proc_macro_error;
 use quote::{format_ident, quote, ToTokens};
+use syn::parse::{Parse, ParseStream, Parser};
+use syn::punctuated::Punctuated;
 use syn::{
-    Attribute, Error, Fields, GenericArgument, GenericParam, Generics, Ident, ItemStruct, Lifetime,
-    PathArguments, Type, Visibility, WhereClause,
+    Attribute, Error, Fields, FieldsNamed, GenericArgument, GenericParam, Generics, Ident,
+    ItemStruct, Lifetime, PathArguments, Token, Type, Visibility,
 };
 
 #[derive(Clone, Copy, PartialEq)]
@@ -35,6 +37,9 @@ struct StructFieldInfo {
     typ: Type,
     field_type: FieldType,
     vis: Visibility,
+    /// The visibility the field was declared with, which vis is derived from. Items generated for
+    /// the field are re-exported from the generated module with this visibility.
+    declared_vis: Visibility,
     borrows: Vec<BorrowRequest>,
     /// If this is true and borrows is empty, the struct will borrow from self in the future but
     /// does not require a builder to be initialized. It should not be able to be removed from the
@@ -44,13 +49,116 @@ struct StructFieldInfo {
     /// Some(false), we should avoid making borrow_* or borrow_*_mut functions as they will not
     /// be able to compile.
     covariant: Option<bool>,
+    /// If this is true, the struct should implement Deref with this field as its target.
+    deref: bool,
+    /// Traits which the struct should implement by forwarding to this field, as specified by
+    /// #[delegate()].
+    delegates: Vec<syn::Path>,
+    /// If this is true, a share_* function should be generated which returns a view containing a
+    /// clone of this field and of the Arc or Rc heads it borrows from.
+    share: bool,
+    /// If this is true, the builder for this field returns the field's value directly instead of a
+    /// Result in try constructors.
+    infallible: bool,
+    /// Set by #[default] or #[default = expr]. The inner value is None when the field should be
+    /// initialized using Default::default(), see make_default_builder.
+    default: Option<Option<syn::Expr>>,
+    /// Indexes of earlier fields, specified by #[peeks()], which the builder for this field is
+    /// given a temporary reference to in addition to the fields it borrows.
+    peeks: Vec<usize>,
+    /// The #[cfg()] attributes on this field, which are copied to every generated item that
+    /// mentions it.
+    cfg_attrs: Vec<Attribute>,
+    /// The doc comments on this field, which are copied to its accessors, builder fields and
+    /// heads field.
+    doc_attrs: Vec<Attribute>,
+    /// Attributes specified by #[accessor_attrs()] which are added to the accessors of this field
+    /// after the ones given to the macro.
+    accessor_attrs: Vec<syn::Meta>,
+    /// If this is true, the field was marked with #[compare] and the traits given to derive()
+    /// should only look at fields marked this way.
+    compare: bool,
 }
 
 impl StructFieldInfo {
+    /// Returns the doc comments written on this field, followed by an empty line if there are any
+    /// so that generated documentation placed after them starts a new paragraph. Nothing is
+    /// returned with no_doc, since the generated items are hidden.
+    fn docs(&self, options: &Options) -> TokenStream2 {
+        if self.doc_attrs.is_empty() || options.do_no_doc {
+            return quote! {};
+        }
+        let doc_attrs = &self.doc_attrs;
+        quote! { #(#doc_attrs)* #[doc = ""] }
+    }
+
+    /// Returns this field's #[cfg()] attributes, to be placed before anything that mentions it.
+    fn cfg(&self) -> TokenStream2 {
+        let cfg_attrs = &self.cfg_attrs;
+        quote! { #(#cfg_attrs)* }
+    }
+
+    /// Returns a predicate which is true when this field is compiled, or None if it always is.
+    fn cfg_predicate(&self) -> Option<TokenStream2> {
+        if self.cfg_attrs.is_empty() {
+            return None;
+        }
+        let predicates = self.cfg_attrs.iter().map(|attr| {
+            match attr.tokens.clone().into_iter().next() {
+                Some(TokenTree::Group(group)) => group.stream(),
+                _ => attr.tokens.clone(),
+            }
+        });
+        Some(quote! { all(#(#predicates),*) })
+    }
+
+    /// Returns the attributes to add to the with_*, borrow_* and with_*_mut functions of this
+    /// field.
+    fn accessor_attrs(&self, options: &Options) -> TokenStream2 {
+        let accessor_attrs = options.accessor_attrs.iter().chain(self.accessor_attrs.iter());
+        quote! { #(#[#accessor_attrs])* }
+    }
+
     fn builder_name(&self) -> Ident {
         format_ident!("{}_builder", self.name)
     }
 
+    fn view_name(&self, struct_name: &Ident) -> Ident {
+        format_ident!(
+            "{}{}View",
+            struct_name,
+            to_class_case(self.name.to_string().as_str())
+        )
+    }
+
+    /// The name of the generic parameter used for the error type of this field's builder when
+    /// per_field_errors is enabled.
+    fn error_param_name(&self) -> Ident {
+        format_ident!("{}Error_", to_class_case(self.name.to_string().as_str()))
+    }
+
+    /// The name of the variant of the build error enum for this field's builder.
+    fn error_variant_name(&self) -> Ident {
+        format_ident!("{}", to_class_case(self.name.to_string().as_str()))
+    }
+
+    /// Returns code which creates a reference to each field this field peeks at. The references
+    /// are only valid until the next time `result` is accessed mutably, so they must be passed
+    /// directly to the builder.
+    fn make_peek_references(
+        &self,
+        field_info: &[StructFieldInfo],
+        result: TokenStream2,
+    ) -> Vec<TokenStream2> {
+        self.peeks
+            .iter()
+            .map(|index| {
+                let peeked_name = &field_info[*index].name;
+                quote! { unsafe { &(*#result.as_ptr()).#peeked_name } }
+            })
+            .collect()
+    }
+
     fn illegal_ref_name(&self) -> Ident {
         format_ident!("{}_illegal_static_reference", self.name)
     }
@@ -93,20 +201,302 @@ impl StructFieldInfo {
 
     /// Generates an error requesting that the user explicitly specify whether or not the
     /// field's type is covariant.
-    fn covariance_error(&self) {
-        let error = concat!(
-            "Ouroboros cannot automatically determine if this type is covariant.\n\n",
-            "If it is covariant, it should be legal to convert any instance of that type to an ",
-            "instance of that type where all usages of 'this are replaced with a smaller ",
-            "lifetime. For example, Box<&'this i32> is covariant because it is legal to use it as ",
-            "a Box<&'a i32> where 'this: 'a. In contrast, Fn(&'this i32) cannot be used as ",
-            "Fn(&'a i32).\n\n",
-            "To resolve this error, add #[covariant] or #[not_covariant] to the field.\n",
+    fn covariance_error(&self, this: &Lifetime) {
+        let error = format!(
+            concat!(
+                "Ouroboros cannot automatically determine if this type is covariant.\n\n",
+                "If it is covariant, it should be legal to convert any instance of that type to ",
+                "an instance of that type where all usages of {0} are replaced with a smaller ",
+                "lifetime. For example, Box<&{0} i32> is covariant because it is legal to use it ",
+                "as a Box<&'a i32> where {0}: 'a. In contrast, Fn(&{0} i32) cannot be used as ",
+                "Fn(&'a i32).\n\n",
+                "To resolve this error, add #[covariant] or #[not_covariant] to the field.\n",
+            ),
+            this
         );
         proc_macro_error::emit_error!(self.typ, error);
     }
 }
 
+/// Lifetimes which lifetime = '... cannot name, since they are built into Rust or used by
+/// generated functions alongside the lifetime of borrowed fields.
+const RESERVED_LIFETIMES: &[&str] = &["static", "_", "outer_borrow", "context_"];
+
+/// Options passed to `#[self_referencing()]`.
+#[derive(Default)]
+struct Options {
+    do_no_doc: bool,
+    do_pub_extras: bool,
+    /// Gives each fallible builder its own error type, see make_build_error_enum.
+    do_per_field_errors: bool,
+    /// A function which is given mutable access to all fields right before the self-referencing
+    /// fields are dropped.
+    on_drop: Option<syn::Path>,
+    /// Attributes to add to every generated builder struct.
+    builder_attrs: Vec<syn::Meta>,
+    /// Attributes to add to every with_*, borrow_* and with_*_mut function, as well as with and
+    /// with_mut.
+    accessor_attrs: Vec<syn::Meta>,
+    /// Attributes to add to the Heads struct.
+    heads_attrs: Vec<syn::Meta>,
+    /// Traits to derive for the Heads struct.
+    heads_derive: Vec<syn::Path>,
+    /// Traits to derive for BorrowedFields and BorrowedMutFields.
+    fields_derive: Vec<syn::Path>,
+    /// Comparison and hashing traits to implement for the struct, see make_compare_impls.
+    derives: Vec<syn::Path>,
+    /// Which of assert_send, not_send, or unsafe_send was specified, if any.
+    send: Option<Ident>,
+    /// Which of assert_sync, not_sync, or unsafe_sync was specified, if any.
+    sync: Option<Ident>,
+    /// Visibilities given to vis(), which take precedence over pub_extras.
+    vis: ItemVisibilities,
+    /// The name of the lifetime used by fields to borrow from other fields, if it is not 'this.
+    lifetime: Option<Lifetime>,
+    /// Writes the expansion of the macro to a file, see write_debug_expansion.
+    do_debug_expand: bool,
+}
+
+/// The visibility of each kind of generated item that was specified with vis(). Each one is
+/// written relative to the module the struct is declared in.
+#[derive(Default)]
+struct ItemVisibilities {
+    /// Builder structs, their fields and their build functions.
+    builder: Option<Visibility>,
+    /// Constructors like new, try_new, from_heads and start, and the error enum of the try
+    /// constructors.
+    new: Option<Visibility>,
+    /// The with function and BorrowedFields.
+    with: Option<Visibility>,
+    /// The with_mut function and BorrowedMutFields.
+    with_mut: Option<Visibility>,
+    /// The into_heads and into_heads_and functions.
+    into_heads: Option<Visibility>,
+    /// The Heads struct and its fields.
+    heads: Option<Visibility>,
+}
+
+impl Parse for ItemVisibilities {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut visibilities = ItemVisibilities::default();
+        while !input.is_empty() {
+            let ident: Ident = input.parse()?;
+            let item = match &ident.to_string()[..] {
+                "builder" => &mut visibilities.builder,
+                "new" => &mut visibilities.new,
+                "with" => &mut visibilities.with,
+                "with_mut" => &mut visibilities.with_mut,
+                "into_heads" => &mut visibilities.into_heads,
+                "heads" => &mut visibilities.heads,
+                _ => return Err(Error::new_spanned(
+                    &ident,
+                    concat!(
+                        "Unknown identifier, expected 'builder', 'new', 'with', 'with_mut', ",
+                        "'into_heads', or 'heads'."
+                    ),
+                )),
+            };
+            if item.is_some() {
+                return Err(Error::new_spanned(
+                    &ident,
+                    format!("The visibility of '{}' can only be specified once.", ident),
+                ));
+            }
+            input.parse::<Token![=]>()?;
+            // Visibility::parse accepts nothing at all as Visibility::Inherited, so private items
+            // are written explicitly.
+            let visibility = if input.peek(Ident) {
+                let private: Ident = input.parse()?;
+                if private != "private" {
+                    return Err(Error::new_spanned(
+                        &private,
+                        "Expected a visibility like 'pub' or 'pub(crate)', or 'private'.",
+                    ));
+                }
+                Visibility::Inherited
+            } else {
+                input.parse()?
+            };
+            *item = Some(visibility);
+            if !input.is_empty() {
+                input.parse::<Token![,]>()?;
+            }
+        }
+        Ok(visibilities)
+    }
+}
+
+impl Options {
+    fn builder_attrs(&self) -> TokenStream2 {
+        let builder_attrs = &self.builder_attrs;
+        quote! { #(#[#builder_attrs])* }
+    }
+
+    fn accessor_attrs(&self) -> TokenStream2 {
+        let accessor_attrs = &self.accessor_attrs;
+        quote! { #(#[#accessor_attrs])* }
+    }
+
+    fn heads_attrs(&self) -> TokenStream2 {
+        let heads_attrs = &self.heads_attrs;
+        let heads_derive = derive_attr(&self.heads_derive);
+        quote! { #heads_derive #(#[#heads_attrs])* }
+    }
+
+    fn fields_attrs(&self) -> TokenStream2 {
+        derive_attr(&self.fields_derive)
+    }
+
+    /// Returns the lifetime used by fields to borrow from other fields, which is 'this unless
+    /// another one was specified with `lifetime = 'name`.
+    fn this_lifetime(&self) -> Lifetime {
+        self.lifetime
+            .clone()
+            .unwrap_or_else(|| Lifetime::new("'this", Span::call_site()))
+    }
+
+    /// Returns the visibility of an item inside the generated module, given the visibility that
+    /// was specified for it with vis(), if any, and the visibility of the struct inside the
+    /// generated module.
+    fn item_visibility(
+        &self,
+        specified: &Option<Visibility>,
+        struct_visibility: &Visibility,
+    ) -> Visibility {
+        if let Some(visibility) = specified {
+            submodule_contents_visiblity(visibility)
+        } else if self.do_pub_extras {
+            struct_visibility.clone()
+        } else {
+            syn::parse_quote! { pub(super) }
+        }
+    }
+
+    /// Like item_visibility, but returns the visibility of the re-export of an item from the
+    /// generated module, given the visibility of the struct as it was declared.
+    fn reexport_visibility(
+        &self,
+        specified: &Option<Visibility>,
+        struct_visibility: &Visibility,
+    ) -> Visibility {
+        if let Some(visibility) = specified {
+            visibility.clone()
+        } else if self.do_pub_extras {
+            struct_visibility.clone()
+        } else {
+            Visibility::Inherited
+        }
+    }
+}
+
+/// Returns a #[derive()] attribute for the given traits, or nothing if there are none.
+fn derive_attr(traits: &[syn::Path]) -> TokenStream2 {
+    if traits.is_empty() {
+        quote! {}
+    } else {
+        quote! { #[derive(#(#traits),*)] }
+    }
+}
+
+/// Parses the parenthesized list of attributes given to options like `builder_attrs(...)`.
+fn parse_attr_list(input: ParseStream) -> syn::Result<Vec<syn::Meta>> {
+    let content;
+    syn::parenthesized!(content in input);
+    let attrs = Punctuated::<syn::Meta, Token![,]>::parse_terminated(&content)?;
+    Ok(attrs.into_iter().collect())
+}
+
+/// Parses the parenthesized list of traits given to options like `heads_derive(...)`.
+fn parse_derive_list(input: ParseStream) -> syn::Result<Vec<syn::Path>> {
+    let content;
+    syn::parenthesized!(content in input);
+    let traits = Punctuated::<syn::Path, Token![,]>::parse_terminated(&content)?;
+    Ok(traits.into_iter().collect())
+}
+
+impl Parse for Options {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut options = Options::default();
+        while !input.is_empty() {
+            let ident: Ident = input.parse()?;
+            match &ident.to_string()[..] {
+                // Chained borrows are now detected automatically, chain_hack is only accepted so
+                // that existing code keeps compiling.
+                "chain_hack" => (),
+                "no_doc" => options.do_no_doc = true,
+                "pub_extras" => options.do_pub_extras = true,
+                "per_field_errors" => options.do_per_field_errors = true,
+                "debug_expand" => options.do_debug_expand = true,
+                "on_drop" => {
+                    input.parse::<Token![=]>()?;
+                    options.on_drop = Some(input.parse()?);
+                }
+                "builder_attrs" => options.builder_attrs.extend(parse_attr_list(input)?),
+                "accessor_attrs" => options.accessor_attrs.extend(parse_attr_list(input)?),
+                "heads_attrs" => options.heads_attrs.extend(parse_attr_list(input)?),
+                "heads_derive" => options.heads_derive.extend(parse_derive_list(input)?),
+                "fields_derive" => options.fields_derive.extend(parse_derive_list(input)?),
+                "derive" => options.derives.extend(parse_derive_list(input)?),
+                "lifetime" => {
+                    input.parse::<Token![=]>()?;
+                    let lifetime: Lifetime = input.parse()?;
+                    if RESERVED_LIFETIMES.contains(&&*lifetime.ident.to_string()) {
+                        return Err(Error::new_spanned(
+                            &lifetime,
+                            format!(
+                                concat!(
+                                    "{} cannot be used for borrowed fields, since it is already ",
+                                    "used by Rust or by the generated code."
+                                ),
+                                lifetime
+                            ),
+                        ));
+                    }
+                    options.lifetime = Some(lifetime);
+                }
+                "vis" => {
+                    let content;
+                    syn::parenthesized!(content in input);
+                    options.vis = content.parse()?;
+                }
+                "assert_send" | "not_send" | "unsafe_send" => {
+                    if options.send.is_some() {
+                        return Err(Error::new_spanned(
+                            &ident,
+                            "Only one of 'assert_send', 'not_send', or 'unsafe_send' can be used.",
+                        ));
+                    }
+                    options.send = Some(ident);
+                }
+                "assert_sync" | "not_sync" | "unsafe_sync" => {
+                    if options.sync.is_some() {
+                        return Err(Error::new_spanned(
+                            &ident,
+                            "Only one of 'assert_sync', 'not_sync', or 'unsafe_sync' can be used.",
+                        ));
+                    }
+                    options.sync = Some(ident);
+                }
+                _ => return Err(Error::new_spanned(
+                    &ident,
+                    concat!(
+                        "Unknown identifier, expected 'chain_hack', 'no_doc', 'pub_extras', ",
+                        "'per_field_errors', 'on_drop = path', 'builder_attrs(...)', ",
+                        "'accessor_attrs(...)', 'heads_attrs(...)', 'heads_derive(...)', ",
+                        "'fields_derive(...)', 'derive(...)', 'assert_send', 'not_send', ",
+                        "'unsafe_send', 'assert_sync', 'not_sync', 'unsafe_sync', 'vis(...)', ",
+                        "'lifetime = 'name', or 'debug_expand'."
+                    ),
+                )),
+            }
+            if !input.is_empty() {
+                input.parse::<Token![,]>()?;
+            }
+        }
+        Ok(options)
+    }
+}
+
 const STD_CONTAINER_TYPES: &[&str] = &["Box", "Arc", "Rc"];
 
 /// Returns Some((type_name, element_type)) if the provided type appears to be Box, Arc, or Rc from
@@ -117,11 +507,7 @@ fn apparent_std_container_type(raw_type: &Type) -> Option<(&'static str, &Type)>
     } else {
         return None;
     };
-    let segment = if let Some(segment) = tpath.path.segments.last() {
-        segment
-    } else {
-        return None;
-    };
+    let segment = tpath.path.segments.last()?;
     let args = if let PathArguments::AngleBracketed(args) = &segment.arguments {
         args
     } else {
@@ -130,7 +516,7 @@ fn apparent_std_container_type(raw_type: &Type) -> Option<(&'static str, &Type)>
     if args.args.len() != 1 {
         return None;
     }
-    let arg = args.args.first().unwrap();
+    let arg = args.args.first()?;
     let eltype = if let GenericArgument::Type(x) = arg {
         x
     } else {
@@ -152,59 +538,73 @@ enum ArgType {
     TraitBound(TokenStream2),
 }
 
-fn deref_type(field_type: &Type, do_chain_hack: bool) -> Result<TokenStream2, Error> {
-    if do_chain_hack {
+/// Returns the type that a builder receives a reference to when it borrows `field`. If the
+/// borrowed field is itself self-referencing, the borrow is part of a chain (field C borrows B which
+/// borrows A.) Rust's type checker has historically been unable to resolve
+/// `<B as Deref>::Target` inside the higher-ranked bounds of such a chain, so when B is a Box, Arc,
+/// or Rc we name the contained type directly instead. make_type_asserts guarantees that these
+/// really are the types from the standard library, any other container in a chain is an error.
+fn deref_type(field: &StructFieldInfo) -> Result<TokenStream2, Error> {
+    let field_type = &field.typ;
+    if field.self_referencing {
         if let Some((_std_type, eltype)) = apparent_std_container_type(field_type) {
             return Ok(quote! { #eltype });
         }
-        Err(Error::new_spanned(
-            &field_type,
+        return Err(Error::new_spanned(
+            field_type,
             concat!(
-                "Borrowed fields must be of type Box<T>, Arc<T>, or Rc<T> when chain_hack is ",
-                "used. Either change the field to one of the listed types or remove chain_hack."
+                "Fields which are borrowed while borrowing other fields themselves must be of ",
+                "type Box<T>, Arc<T>, or Rc<T>. Either change the field to one of the listed ",
+                "types or borrow the field it borrows from directly."
             ),
-        ))
-    } else {
-        Ok(quote! { <#field_type as ::core::ops::Deref>::Target })
+        ));
     }
+    Ok(quote! { <#field_type as ::core::ops::Deref>::Target })
 }
 
+/// If `context_type` is provided, builders receive a reference to it before the fields they borrow.
 fn make_constructor_arg_type_impl(
     for_field: &StructFieldInfo,
     other_fields: &[StructFieldInfo],
     fake_lifetime: &Ident,
+    this: &Lifetime,
+    context_type: Option<&Ident>,
     make_builder_return_type: impl FnOnce() -> TokenStream2,
-    do_chain_hack: bool,
 ) -> Result<ArgType, Error> {
     let field_type = &for_field.typ;
     if for_field.borrows.is_empty() {
         // Even if self_referencing is true, as long as borrows is empty, we don't need to use a
         // builder to construct it.
         let field_type =
-            replace_this_with_lifetime(field_type.into_token_stream(), fake_lifetime.clone());
+            replace_this_with_lifetime(field_type.into_token_stream(), this, fake_lifetime.clone());
         Ok(ArgType::Plain(quote! { #field_type }))
     } else {
         let mut field_builder_params = Vec::new();
+        if let Some(context_type) = context_type {
+            field_builder_params.push(quote! { &#context_type });
+        }
         for borrow in &for_field.borrows {
             if borrow.mutable {
                 let field = &other_fields[borrow.index];
-                let field_type = &field.typ;
-                let content_type = deref_type(field_type, do_chain_hack)?;
+                let content_type = deref_type(field)?;
                 field_builder_params.push(quote! {
-                    &'this mut #content_type
+                    &#this mut #content_type
                 });
             } else {
                 let field = &other_fields[borrow.index];
-                let field_type = &field.typ;
-                let content_type = deref_type(field_type, do_chain_hack)?;
+                let content_type = deref_type(field)?;
                 field_builder_params.push(quote! {
-                    &'this #content_type
+                    &#this #content_type
                 });
             }
         }
+        for index in &for_field.peeks {
+            let peeked_type = &other_fields[*index].typ;
+            field_builder_params.push(quote! { &#peeked_type });
+        }
         let return_type = make_builder_return_type();
         let bound =
-            quote! { for<'this> ::core::ops::FnOnce(#(#field_builder_params),*) -> #return_type };
+            quote! { for<#this> ::core::ops::FnOnce(#(#field_builder_params),*) -> #return_type };
         Ok(ArgType::TraitBound(bound))
     }
 }
@@ -215,13 +615,14 @@ fn make_constructor_arg_type(
     for_field: &StructFieldInfo,
     other_fields: &[StructFieldInfo],
     fake_lifetime: &Ident,
-    do_chain_hack: bool,
+    this: &Lifetime,
+    context_type: Option<&Ident>,
     make_async: bool,
 ) -> Result<ArgType, Error> {
     let field_type = &for_field.typ;
     let return_ty_constructor = || {
         if make_async {
-            quote! { ::std::pin::Pin<::std::boxed::Box<dyn ::core::future::Future<Output=#field_type> + 'this>> }
+            quote! { ::std::pin::Pin<::std::boxed::Box<dyn ::core::future::Future<Output=#field_type> + #this>> }
         } else {
             quote! { #field_type }
         }
@@ -230,33 +631,49 @@ fn make_constructor_arg_type(
         for_field,
         other_fields,
         fake_lifetime,
+        this,
+        context_type,
         return_ty_constructor,
-        do_chain_hack,
     )
 }
 
-/// Like make_constructor_arg_type, but used for the try_new constructor.
+/// Like make_constructor_arg_type, but used for the try_new constructor. `error_type` is the type
+/// of error the builder can return. Builders for fields marked #[infallible] return the same types
+/// as in the regular constructor.
 fn make_try_constructor_arg_type(
     for_field: &StructFieldInfo,
     other_fields: &[StructFieldInfo],
     fake_lifetime: &Ident,
-    do_chain_hack: bool,
+    this: &Lifetime,
+    error_type: &Ident,
+    context_type: Option<&Ident>,
     make_async: bool,
 ) -> Result<ArgType, Error> {
+    if for_field.infallible {
+        return make_constructor_arg_type(
+            for_field,
+            other_fields,
+            fake_lifetime,
+            this,
+            context_type,
+            make_async,
+        );
+    }
     let field_type = &for_field.typ;
     let return_ty_constructor = || {
         if make_async {
-            quote! { ::std::pin::Pin<::std::boxed::Box<dyn ::core::future::Future<Output=::core::result::Result<#field_type, Error_>> + 'this>> }
+            quote! { ::std::pin::Pin<::std::boxed::Box<dyn ::core::future::Future<Output=::core::result::Result<#field_type, #error_type>> + #this>> }
         } else {
-            quote! { ::core::result::Result<#field_type, Error_> }
+            quote! { ::core::result::Result<#field_type, #error_type> }
         }
     };
     make_constructor_arg_type_impl(
         for_field,
         other_fields,
         fake_lifetime,
+        this,
+        context_type,
         return_ty_constructor,
-        do_chain_hack,
     )
 }
 
@@ -266,49 +683,234 @@ fn make_template_consumers(generics: &Generics) -> impl Iterator<Item = (TokenSt
         .params
         .clone()
         .into_iter()
-        .map(|param| match param {
+        .filter_map(|param| match param {
             GenericParam::Type(ty) => {
                 let ident = &ty.ident;
-                (
+                Some((
                     quote! { #ident },
                     format_ident!(
                         "_consume_template_type_{}",
                         ident.to_string().to_snake_case()
                     ),
-                )
+                ))
             }
             GenericParam::Lifetime(lt) => {
                 let lifetime = &lt.lifetime;
                 let ident = &lifetime.ident;
-                (
+                Some((
                     quote! { &#lifetime () },
                     format_ident!("_consume_template_lifetime_{}", ident),
-                )
+                ))
             }
-            GenericParam::Const(..) => unimplemented!(),
+            // Rejected by reject_const_generics.
+            GenericParam::Const(..) => None,
         })
 }
 
-fn replace_this_with_lifetime(input: TokenStream2, lifetime: Ident) -> TokenStream2 {
+/// Replaces every use of the `this` lifetime (usually 'this) with `lifetime`. Identifiers which
+/// are not part of a lifetime are left alone, even if they have the same name.
+fn replace_this_with_lifetime(
+    input: TokenStream2,
+    this: &Lifetime,
+    lifetime: Ident,
+) -> TokenStream2 {
+    let mut after_apostrophe = false;
     input
         .into_iter()
-        .map(|token| match &token {
-            TokenTree::Ident(ident) => {
-                if ident == "this" {
-                    TokenTree::Ident(lifetime.clone())
-                } else {
+        .map(|token| {
+            let is_lifetime = after_apostrophe;
+            after_apostrophe = false;
+            match &token {
+                TokenTree::Punct(punct) => {
+                    after_apostrophe = punct.as_char() == '\'';
                     token
                 }
+                TokenTree::Ident(ident) if is_lifetime && *ident == this.ident => {
+                    TokenTree::Ident(lifetime.clone())
+                }
+                TokenTree::Group(group) => TokenTree::Group(Group::new(
+                    group.delimiter(),
+                    replace_this_with_lifetime(group.stream(), this, lifetime.clone()),
+                )),
+                _ => token,
             }
-            TokenTree::Group(group) => TokenTree::Group(Group::new(
-                group.delimiter(),
-                replace_this_with_lifetime(group.stream(), lifetime.clone()),
-            )),
-            _ => token,
         })
         .collect()
 }
 
+/// Returns true if the `this` lifetime (usually 'this) appears anywhere in the given tokens.
+fn uses_this_lifetime(input: TokenStream2, this: &Lifetime) -> bool {
+    let mut after_apostrophe = false;
+    for token in input {
+        match &token {
+            TokenTree::Punct(punct) => {
+                after_apostrophe = punct.as_char() == '\'';
+                continue;
+            }
+            TokenTree::Ident(ident) => {
+                if after_apostrophe && *ident == this.ident {
+                    return true;
+                }
+            }
+            TokenTree::Group(group) => {
+                if uses_this_lifetime(group.stream(), this) {
+                    return true;
+                }
+            }
+            TokenTree::Literal(..) => (),
+        }
+        after_apostrophe = false;
+    }
+    false
+}
+
+/// The attributes on fields which are handled by ouroboros.
+const FIELD_ATTRIBUTES: &[&str] = &[
+    "borrows",
+    "covariant",
+    "not_covariant",
+    "deref",
+    "delegate",
+    "share",
+    "infallible",
+    "default",
+    "peeks",
+    "accessor_attrs",
+    "compare",
+];
+
+/// Returns an error if a #[cfg_attr()] on a field would add one of the attributes in
+/// FIELD_ATTRIBUTES. Those are read before #[cfg_attr()] is expanded, so they would otherwise be
+/// left on the actual struct where the compiler does not recognize them.
+fn check_cfg_attr(attr: &Attribute) -> Result<(), Error> {
+    let parser = |input: ParseStream| {
+        input.parse::<syn::NestedMeta>()?;
+        let mut paths = Vec::new();
+        while !input.is_empty() {
+            input.parse::<Token![,]>()?;
+            if input.is_empty() {
+                break;
+            }
+            paths.push(input.parse::<syn::Path>()?);
+            while !input.is_empty() && !input.peek(Token![,]) {
+                input.parse::<TokenTree>()?;
+            }
+        }
+        Ok(paths)
+    };
+    // Malformed attributes are left for the compiler to report.
+    let paths = if let Ok(paths) = attr.parse_args_with(parser) {
+        paths
+    } else {
+        return Ok(());
+    };
+    for path in paths {
+        if FIELD_ATTRIBUTES.iter().any(|name| path.is_ident(name)) {
+            return Err(Error::new_spanned(
+                &path,
+                concat!(
+                    "Attributes handled by ouroboros cannot be used inside #[cfg_attr()], since ",
+                    "they are read before it is expanded. Put #[cfg()] on the whole field instead."
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// A predicate from a #[cfg()] attribute, used to check that a field is only compiled when the
+/// fields it uses are.
+enum CfgPredicate {
+    All(Vec<CfgPredicate>),
+    Any(Vec<CfgPredicate>),
+    Not(Box<CfgPredicate>),
+    /// A configuration option such as `unix` or `feature = "std"`, identified by its tokens.
+    Option(String),
+}
+
+impl CfgPredicate {
+    fn from_attr(attr: &Attribute) -> Self {
+        match attr.parse_meta() {
+            Ok(syn::Meta::List(list)) if list.nested.len() == 1 => {
+                Self::from_nested(&list.nested[0])
+            }
+            _ => Self::Option(attr.tokens.to_string()),
+        }
+    }
+
+    fn from_nested(nested: &syn::NestedMeta) -> Self {
+        let list = match nested {
+            syn::NestedMeta::Meta(syn::Meta::List(list)) => list,
+            _ => return Self::Option(nested.to_token_stream().to_string()),
+        };
+        let mut operands = list.nested.iter().map(Self::from_nested).collect::<Vec<_>>();
+        if list.path.is_ident("all") {
+            Self::All(operands)
+        } else if list.path.is_ident("any") {
+            Self::Any(operands)
+        } else if list.path.is_ident("not") && operands.len() == 1 {
+            Self::Not(Box::new(operands.remove(0)))
+        } else {
+            Self::Option(nested.to_token_stream().to_string())
+        }
+    }
+
+    fn add_options<'a>(&'a self, options: &mut Vec<&'a str>) {
+        match self {
+            Self::All(operands) | Self::Any(operands) => {
+                for operand in operands {
+                    operand.add_options(options);
+                }
+            }
+            Self::Not(operand) => operand.add_options(options),
+            Self::Option(option) => {
+                if !options.contains(&option.as_str()) {
+                    options.push(option);
+                }
+            }
+        }
+    }
+
+    fn holds(&self, enabled: &dyn Fn(&str) -> bool) -> bool {
+        match self {
+            Self::All(operands) => operands.iter().all(|operand| operand.holds(enabled)),
+            Self::Any(operands) => operands.iter().any(|operand| operand.holds(enabled)),
+            Self::Not(operand) => !operand.holds(enabled),
+            Self::Option(option) => enabled(option),
+        }
+    }
+}
+
+/// The largest number of distinct configuration options for which cfg_implies tries every
+/// combination of them.
+const MAX_CFG_OPTIONS: usize = 16;
+
+/// Returns true if every combination of configuration options which satisfies all the #[cfg()]
+/// attributes in `condition` also satisfies the ones in `required`.
+fn cfg_implies(condition: &[Attribute], required: &[Attribute]) -> bool {
+    let condition_predicate =
+        CfgPredicate::All(condition.iter().map(CfgPredicate::from_attr).collect());
+    let required_predicate =
+        CfgPredicate::All(required.iter().map(CfgPredicate::from_attr).collect());
+    let mut options = Vec::new();
+    condition_predicate.add_options(&mut options);
+    required_predicate.add_options(&mut options);
+    if options.len() > MAX_CFG_OPTIONS {
+        // Too many to try every combination, so only accept attributes which are repeated as is.
+        return required.iter().all(|attr| {
+            let attr = attr.to_token_stream().to_string();
+            condition.iter().any(|own| own.to_token_stream().to_string() == attr)
+        });
+    }
+    (0..1u64 << options.len()).all(|combination| {
+        let enabled = |option: &str| {
+            let index = options.iter().position(|o| *o == option).unwrap();
+            combination & (1 << index) != 0
+        };
+        !condition_predicate.holds(&enabled) || required_predicate.holds(&enabled)
+    })
+}
+
 fn handle_borrows_attr(
     field_info: &mut [StructFieldInfo],
     attr: &Attribute,
@@ -398,31 +1000,31 @@ fn handle_borrows_attr(
 }
 
 /// Returns true if the specified type can be assumed to be covariant.
-fn type_is_covariant(ty: &syn::Type, in_template: bool) -> bool {
+fn type_is_covariant(ty: &syn::Type, this: &Lifetime, in_template: bool) -> bool {
     use syn::Type::*;
     match ty {
-        Array(arr) => type_is_covariant(&*arr.elem, in_template),
+        Array(arr) => type_is_covariant(&arr.elem, this, in_template),
         BareFn(f) => {
             for arg in f.inputs.iter() {
-                if !type_is_covariant(&arg.ty, true) {
+                if !type_is_covariant(&arg.ty, this, true) {
                     return false;
                 }
             }
             if let syn::ReturnType::Type(_, ty) = &f.output {
-                type_is_covariant(ty, true)
+                type_is_covariant(ty, this, true)
             } else {
                 true
             }
         }
-        Group(ty) => type_is_covariant(&ty.elem, in_template),
+        Group(ty) => type_is_covariant(&ty.elem, this, in_template),
         ImplTrait(..) => false, // Unusable in struct definition.
         Infer(..) => false,     // Unusable in struct definition.
         Macro(..) => false,     // Assume false since we don't know.
         Never(..) => false,
-        Paren(ty) => type_is_covariant(&ty.elem, in_template),
+        Paren(ty) => type_is_covariant(&ty.elem, this, in_template),
         Path(path) => {
             if let Some(qself) = &path.qself {
-                if !type_is_covariant(&qself.ty, in_template) {
+                if !type_is_covariant(&qself.ty, this, in_template) {
                     return false;
                 }
             }
@@ -436,23 +1038,23 @@ fn type_is_covariant(ty: &syn::Type, in_template: bool) -> bool {
                 if let syn::PathArguments::AngleBracketed(args) = &args {
                     for arg in args.args.iter() {
                         if let syn::GenericArgument::Type(ty) = arg {
-                            if !type_is_covariant(ty, !is_covariant) {
+                            if !type_is_covariant(ty, this, !is_covariant) {
                                 return false;
                             }
                         } else if let syn::GenericArgument::Lifetime(lt) = arg {
-                            if lt.ident.to_string() == "this" && !is_covariant {
+                            if lt.ident == this.ident && !is_covariant {
                                 return false;
                             }
                         }
                     }
                 } else if let syn::PathArguments::Parenthesized(args) = &args {
                     for arg in args.inputs.iter() {
-                        if !type_is_covariant(arg, true) {
+                        if !type_is_covariant(arg, this, true) {
                             return false;
                         }
                     }
                     if let syn::ReturnType::Type(_, ty) = &args.output {
-                        if !type_is_covariant(ty, true) {
+                        if !type_is_covariant(ty, this, true) {
                             return false;
                         }
                     }
@@ -460,23 +1062,23 @@ fn type_is_covariant(ty: &syn::Type, in_template: bool) -> bool {
             }
             true
         }
-        Ptr(ptr) => type_is_covariant(&ptr.elem, in_template),
+        Ptr(ptr) => type_is_covariant(&ptr.elem, this, in_template),
         // Ignore the actual lifetime of the reference because Rust can automatically convert those.
-        Reference(rf) => !in_template && type_is_covariant(&rf.elem, in_template),
-        Slice(sl) => type_is_covariant(&sl.elem, in_template),
+        Reference(rf) => !in_template && type_is_covariant(&rf.elem, this, in_template),
+        Slice(sl) => type_is_covariant(&sl.elem, this, in_template),
         // I don't think this is reachable but panic just in case.
         TraitObject(..) => false,
         Tuple(tup) => {
             for ty in tup.elems.iter() {
-                if !type_is_covariant(ty, in_template) {
+                if !type_is_covariant(ty, this, in_template) {
                     return false;
                 }
             }
             false
         }
-        // As of writing this, syn parses all the types we could need.
-        Verbatim(..) => unimplemented!(),
-        _ => unimplemented!(),
+        // As of writing this, syn parses all the types we could need. Anything else is assumed not
+        // to be covariant, so the user is asked to specify it.
+        _ => false,
     }
 }
 
@@ -486,59 +1088,189 @@ fn type_is_covariant(ty: &syn::Type, in_template: bool) -> bool {
 fn create_actual_struct(
     visibility: &Visibility,
     original_struct_def: &ItemStruct,
+    options: &Options,
 ) -> Result<(TokenStream2, Ident, Vec<StructFieldInfo>), Error> {
+    let this = options.this_lifetime();
     let mut actual_struct_def = original_struct_def.clone();
     actual_struct_def.vis = visibility.clone();
     let mut field_info = Vec::new();
+    let mut has_deref = false;
     match &mut actual_struct_def.fields {
         Fields::Named(fields) => {
             for field in &mut fields.named {
                 let mut borrows = Vec::new();
                 let mut self_referencing = false;
-                let covariant = type_is_covariant(&field.ty, false);
+                let mut deref = false;
+                let mut delegates = Vec::new();
+                let mut share = false;
+                let mut infallible = false;
+                let mut default = None;
+                let mut peeks = Vec::new();
+                let mut cfg_attrs = Vec::new();
+                let mut doc_attrs = Vec::new();
+                let mut accessor_attrs = Vec::new();
+                let mut compare = false;
+                let covariant = type_is_covariant(&field.ty, &this, false);
                 let mut covariant = if covariant { Some(true) } else { None };
                 let mut remove_attrs = Vec::new();
                 for (index, attr) in field.attrs.iter().enumerate() {
-                    let path = &attr.path;
-                    if path.leading_colon.is_some() {
+                    let attr_name = if let Some(ident) = attr.path.get_ident() {
+                        ident
+                    } else {
                         continue;
+                    };
+                    if attr_name == "cfg" {
+                        cfg_attrs.push(attr.clone());
                     }
-                    if path.segments.len() != 1 {
-                        continue;
+                    if attr_name == "doc" {
+                        doc_attrs.push(attr.clone());
                     }
-                    if path.segments.first().unwrap().ident == "borrows" {
+                    if attr_name == "cfg_attr" {
+                        check_cfg_attr(attr)?;
+                    }
+                    if attr_name == "borrows" {
                         if self_referencing {
-                            panic!("TODO: Nice error, used #[borrows()] twice.");
+                            return Err(Error::new_spanned(
+                                attr,
+                                concat!(
+                                    "A field can only have one #[borrows()] annotation, list ",
+                                    "everything it borrows in the first one instead: ",
+                                    "#[borrows(a, mut b)]"
+                                ),
+                            ));
                         }
                         self_referencing = true;
                         handle_borrows_attr(&mut field_info[..], attr, &mut borrows)?;
                         remove_attrs.push(index);
                     }
-                    if path.segments.first().unwrap().ident == "covariant" {
+                    if attr_name == "covariant" {
                         covariant = Some(true);
                         remove_attrs.push(index);
                     }
-                    if path.segments.first().unwrap().ident == "not_covariant" {
+                    if attr_name == "not_covariant" {
                         covariant = Some(false);
                         remove_attrs.push(index);
                     }
+                    if attr_name == "deref" {
+                        if has_deref {
+                            return Err(Error::new_spanned(
+                                attr,
+                                "Only one field of a struct can be marked with #[deref].",
+                            ));
+                        }
+                        has_deref = true;
+                        deref = true;
+                        remove_attrs.push(index);
+                    }
+                    if attr_name == "delegate" {
+                        let traits = attr.parse_args_with(
+                            Punctuated::<syn::Path, Token![,]>::parse_terminated,
+                        )?;
+                        delegates.extend(traits);
+                        remove_attrs.push(index);
+                    }
+                    if attr_name == "share" {
+                        share = true;
+                        remove_attrs.push(index);
+                    }
+                    if attr_name == "infallible" {
+                        infallible = true;
+                        remove_attrs.push(index);
+                    }
+                    if attr_name == "compare" {
+                        compare = true;
+                        remove_attrs.push(index);
+                    }
+                    if attr_name == "default" {
+                        if default.is_some() {
+                            return Err(Error::new_spanned(
+                                attr,
+                                "A field can only have one #[default] annotation.",
+                            ));
+                        }
+                        default = Some(if attr.tokens.is_empty() {
+                            None
+                        } else {
+                            let parser = |input: ParseStream| {
+                                input.parse::<Token![=]>()?;
+                                input.parse::<syn::Expr>()
+                            };
+                            Some(parser.parse2(attr.tokens.clone())?)
+                        });
+                        remove_attrs.push(index);
+                    }
+                    if attr_name == "accessor_attrs" {
+                        let attrs = attr.parse_args_with(
+                            Punctuated::<syn::Meta, Token![,]>::parse_terminated,
+                        )?;
+                        accessor_attrs.extend(attrs);
+                        remove_attrs.push(index);
+                    }
+                    if attr_name == "peeks" {
+                        let names = attr.parse_args_with(
+                            Punctuated::<Ident, Token![,]>::parse_terminated,
+                        )?;
+                        for name in names {
+                            let index = field_info
+                                .iter()
+                                .position(|item| item.name == name);
+                            let index = index.ok_or_else(|| {
+                                Error::new_spanned(
+                                    &name,
+                                    concat!(
+                                        "Unknown identifier, make sure that it is spelled ",
+                                        "correctly and defined above the location it is peeked at."
+                                    ),
+                                )
+                            })?;
+                            peeks.push(index);
+                        }
+                        remove_attrs.push(index);
+                    }
                 }
                 for index in remove_attrs.into_iter().rev() {
                     field.attrs.remove(index);
                 }
+                if !self_referencing && uses_this_lifetime(field.ty.to_token_stream(), &this) {
+                    proc_macro_error::emit_error!(
+                        field.ty,
+                        "{} can only be used in fields which borrow from other fields.", this;
+                        help = concat!(
+                            "add #[borrows(...)] above this field listing the fields it borrows, ",
+                            "or #[borrows()] if it does not borrow anything when it is created"
+                        )
+                    );
+                }
+                let name = if let Some(name) = &field.ident {
+                    name.clone()
+                } else {
+                    return Err(Error::new_spanned(&field, "Expected a named field."));
+                };
                 field.attrs.push(syn::parse_quote! { #[doc(hidden)] });
                 // We should not be able to access the field outside of the hidden module where
                 // everything is generated.
-                let with_vis = submodule_contents_visiblity(&field.vis.clone());
+                let declared_vis = field.vis.clone();
+                let with_vis = submodule_contents_visiblity(&declared_vis);
                 field.vis = syn::Visibility::Inherited;
                 field_info.push(StructFieldInfo {
-                    name: field.ident.clone().expect("Named field has no name."),
+                    name,
                     typ: field.ty.clone(),
                     field_type: FieldType::Tail,
                     vis: with_vis,
+                    declared_vis,
                     borrows,
                     self_referencing,
                     covariant,
+                    deref,
+                    delegates,
+                    share,
+                    infallible,
+                    default,
+                    peeks,
+                    cfg_attrs,
+                    doc_attrs,
+                    accessor_attrs,
+                    compare,
                 });
             }
         }
@@ -555,6 +1287,60 @@ fn create_actual_struct(
             ))
         }
     }
+    // Misuses of 'this would cause confusing errors later on, so stop here if there were any.
+    proc_macro_error::abort_if_dirty();
+    for field in &field_info {
+        if !field.peeks.is_empty() && field.borrows.is_empty() {
+            return Err(Error::new_spanned(
+                &field.typ,
+                concat!(
+                    "#[peeks()] can only be used on fields which borrow from other fields, since ",
+                    "other fields are passed to constructors directly."
+                ),
+            ));
+        }
+        for index in &field.peeks {
+            let peeked = &field_info[*index];
+            if peeked.field_type == FieldType::BorrowedMut {
+                return Err(Error::new_spanned(
+                    &field.name,
+                    format!(
+                        concat!(
+                            "Cannot peek at `{0}` from `{1}` because `{0}` is mutably borrowed ",
+                            "by another field."
+                        ),
+                        peeked.name, field.name
+                    ),
+                ));
+            }
+        }
+        if field.infallible && field.borrows.is_empty() {
+            return Err(Error::new_spanned(
+                &field.typ,
+                concat!(
+                    "#[infallible] can only be used on fields which borrow from other fields, ",
+                    "since other fields are passed to constructors directly."
+                ),
+            ));
+        }
+        let used_indexes = field.borrows.iter().map(|borrow| borrow.index);
+        for index in used_indexes.chain(field.peeks.iter().copied()) {
+            let used = &field_info[index];
+            if !cfg_implies(&field.cfg_attrs, &used.cfg_attrs) {
+                let used_cfg = used.cfg().to_string();
+                return Err(Error::new_spanned(
+                    &field.name,
+                    format!(
+                        concat!(
+                            "`{0}` uses `{1}`, which is only compiled with {2}, so `{0}` ",
+                            "needs #[cfg()] attributes which imply the same."
+                        ),
+                        field.name, used.name, used_cfg
+                    ),
+                ));
+            }
+        }
+    }
     if field_info.len() < 2 {
         return Err(Error::new(
             Span::call_site(),
@@ -571,7 +1357,7 @@ fn create_actual_struct(
     if !has_non_tail {
         return Err(Error::new(
             Span::call_site(),
-            &format!(
+            format!(
                 concat!(
                     "Self-referencing struct cannot be made entirely of tail fields, try adding ",
                     "#[borrows({0})] to a field defined after {0}."
@@ -583,13 +1369,23 @@ fn create_actual_struct(
     // Reverse the order of all fields. We ensure that items in the struct are only dependent
     // on references to items above them. Rust drops items in a struct in forward declaration order.
     // This would cause parents being dropped before children, necessitating the reversal.
-    match &mut actual_struct_def.fields {
-        Fields::Named(fields) => {
-            let reversed = fields.named.iter().rev().cloned().collect();
-            fields.named = reversed;
+    // Other kinds of fields were rejected above.
+    if let Fields::Named(fields) = &mut actual_struct_def.fields {
+        let reversed = fields.named.iter().rev().cloned().collect();
+        fields.named = reversed;
+        // The markers are zero-sized, so the constructors can leave them uninitialized.
+        if options.send.as_ref().map(|ident| ident == "not_send") == Some(true) {
+            let marker: FieldsNamed = syn::parse_quote! {
+                { _ouroboros_not_send: ::ouroboros::macro_help::NotSend }
+            };
+            fields.named.extend(marker.named);
+        }
+        if options.sync.as_ref().map(|ident| ident == "not_sync") == Some(true) {
+            let marker: FieldsNamed = syn::parse_quote! {
+                { _ouroboros_not_sync: ::ouroboros::macro_help::NotSync }
+            };
+            fields.named.extend(marker.named);
         }
-        Fields::Unnamed(_fields) => unreachable!("Error handled earlier."),
-        Fields::Unit => unreachable!("Error handled earlier."),
     }
 
     let fake_lifetime =
@@ -601,7 +1397,7 @@ fn create_actual_struct(
 
     // Finally, replace the fake 'this lifetime with 'static.
     let actual_struct_def =
-        replace_this_with_lifetime(quote! { #actual_struct_def }, fake_lifetime.clone());
+        replace_this_with_lifetime(quote! { #actual_struct_def }, &this, fake_lifetime.clone());
 
     Ok((actual_struct_def, fake_lifetime, field_info))
 }
@@ -619,32 +1415,216 @@ fn make_generic_arguments(generic_params: &Generics) -> Vec<TokenStream2> {
                 let lifetime = &lt.lifetime;
                 arguments.push(quote! { #lifetime });
             }
-            GenericParam::Const(_) => unimplemented!("Const generics are not supported yet."),
+            // Rejected by reject_const_generics.
+            GenericParam::Const(..) => (),
         }
     }
     arguments
 }
 
-fn create_builder_and_constructor(
-    struct_visibility: &Visibility,
-    struct_name: &Ident,
-    builder_struct_name: &Ident,
-    fake_lifetime: &Ident,
+/// Const generics are not supported yet, report them instead of generating broken code.
+fn reject_const_generics(generic_params: &Generics) -> Result<(), Error> {
+    for param in &generic_params.params {
+        if let GenericParam::Const(constant) = param {
+            return Err(Error::new_spanned(
+                constant,
+                "Const generics are not supported yet.",
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// The lifetime given to `lifetime = 'name` refers to the borrowed fields, so it cannot also be one
+/// of the struct's own lifetime parameters.
+fn reject_lifetime_parameter_option(
     generic_params: &Generics,
-    generic_args: &[TokenStream2],
+    options: &Options,
+) -> Result<(), Error> {
+    let lifetime = match &options.lifetime {
+        Some(lifetime) => lifetime,
+        None => return Ok(()),
+    };
+    if generic_params.lifetimes().any(|param| param.lifetime.ident == lifetime.ident) {
+        return Err(Error::new_spanned(
+            lifetime,
+            format!(
+                concat!(
+                    "{} cannot be used for borrowed fields, since it is already a lifetime ",
+                    "parameter of the struct."
+                ),
+                lifetime
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// The parts of a constructor's parameters which are needed to build the struct from an existing
+/// `Heads` value instead of from individual head fields.
+struct FromHeadsParts {
+    /// Parameters for the fields which are not heads.
+    params: Vec<TokenStream2>,
+    /// Fields of a regular builder struct literal, taking head fields from a variable named
+    /// `heads`.
+    builder_literal_fields: Vec<TokenStream2>,
+    /// Fields of the heads builder struct, excluding `heads` itself.
+    builder_fields: Vec<TokenStream2>,
+    builder_field_names: Vec<TokenStream2>,
+}
+
+/// Splits the per-field parameters of a constructor into the ones that come from `Heads` and the
+/// ones that still have to be passed in directly.
+fn make_from_heads_parts(
     field_info: &[StructFieldInfo],
-    do_chain_hack: bool,
-    do_no_doc: bool,
-    do_pub_extras: bool,
-    make_async: bool,
-) -> Result<(TokenStream2, TokenStream2), Error> {
-    let visibility = if do_pub_extras {
-        struct_visibility.clone()
-    } else {
-        syn::parse_quote! { pub(super) }
+    params: &[TokenStream2],
+    builder_struct_fields: &[TokenStream2],
+    builder_struct_field_names: &[TokenStream2],
+) -> FromHeadsParts {
+    let mut parts = FromHeadsParts {
+        params: Vec::new(),
+        builder_literal_fields: Vec::new(),
+        builder_fields: Vec::new(),
+        builder_field_names: Vec::new(),
     };
-    let documentation = format!(
-        concat!(
+    for (index, field) in field_info.iter().enumerate() {
+        if field.self_referencing {
+            parts.params.push(params[index].clone());
+            parts.builder_literal_fields.push(builder_struct_field_names[index].clone());
+            parts.builder_fields.push(builder_struct_fields[index].clone());
+            parts.builder_field_names.push(builder_struct_field_names[index].clone());
+        } else {
+            let field_name = &field.name;
+            let cfg = field.cfg();
+            parts.builder_literal_fields.push(quote! { #cfg #field_name: heads.#field_name });
+        }
+    }
+    parts
+}
+
+/// Returns the name of the builder which constructs the struct from `Heads`, given the name of
+/// the regular builder it corresponds to. E.G. `MyStructTryBuilder` becomes
+/// `MyStructTryHeadsBuilder`.
+fn heads_builder_name(builder_struct_name: &Ident) -> Ident {
+    let name = builder_struct_name.to_string();
+    format_ident!("{}HeadsBuilder", name.trim_end_matches("Builder"))
+}
+
+/// The generic parameters of a builder struct. Parameters for the builders of fields with #[cfg()]
+/// attributes only exist when the field does. The parameters of `impl<...>` can be conditionally
+/// compiled but the arguments in `Builder<...>` cannot, so they are collected by a chain of
+/// helper macros with one conditionally compiled definition per such parameter.
+struct BuilderGenerics {
+    producers: Vec<TokenStream2>,
+    consumers: Vec<TokenStream2>,
+    /// The predicate under which each parameter exists, or None if it always does.
+    predicates: Vec<Option<TokenStream2>>,
+}
+
+impl BuilderGenerics {
+    fn new(producers: Vec<TokenStream2>, consumers: Vec<TokenStream2>) -> Self {
+        let predicates = vec![None; producers.len()];
+        Self {
+            producers,
+            consumers,
+            predicates,
+        }
+    }
+
+    fn push(
+        &mut self,
+        producer: TokenStream2,
+        consumer: TokenStream2,
+        predicate: Option<TokenStream2>,
+    ) {
+        self.producers.push(producer);
+        self.consumers.push(consumer);
+        self.predicates.push(predicate);
+    }
+
+    /// Returns the parameters to use in the definition of the builder struct.
+    fn params(&self) -> Vec<TokenStream2> {
+        self.producers
+            .iter()
+            .zip(self.predicates.iter())
+            .map(|(producer, predicate)| match predicate {
+                Some(predicate) => quote! { #[cfg(#predicate)] #producer },
+                None => quote! { #producer },
+            })
+            .collect()
+    }
+
+    /// Calls `make_impl` with the producers and consumers of the parameters and returns the
+    /// result. When some parameters are conditional, `make_impl` is given a single consumer which
+    /// expands to the arguments that exist, and the result is emitted through helper macros named
+    /// after `impl_name`.
+    fn make_impls(
+        &self,
+        impl_name: &Ident,
+        make_impl: impl Fn(&[TokenStream2], &[TokenStream2]) -> TokenStream2,
+    ) -> TokenStream2 {
+        if self.predicates.iter().all(Option::is_none) {
+            return make_impl(&self.producers, &self.consumers);
+        }
+        let mut macros = Vec::new();
+        let mut pending = Vec::new();
+        let mut step = 0;
+        let step_name = |step: usize| format_ident!("__ouroboros_impl_{}_{}", impl_name, step);
+        for (consumer, predicate) in self.consumers.iter().zip(self.predicates.iter()) {
+            let predicate = match predicate {
+                Some(predicate) => predicate,
+                None => {
+                    pending.push(consumer.clone());
+                    continue;
+                }
+            };
+            let name = step_name(step);
+            let next = step_name(step + 1);
+            macros.push(quote! {
+                #[cfg(#predicate)]
+                macro_rules! #name {
+                    ([$($args:tt)*]) => { #next! { [$($args)* #(#pending,)* #consumer,] } };
+                }
+                #[cfg(not(#predicate))]
+                macro_rules! #name {
+                    ([$($args:tt)*]) => { #next! { [$($args)* #(#pending,)*] } };
+                }
+            });
+            pending.clear();
+            step += 1;
+        }
+        let first = step_name(0);
+        let last = step_name(step);
+        let impl_def = make_impl(&self.params(), &[quote! { $($args)* #(#pending),* }]);
+        // Lints treat code from these macros as local, unlike the rest of the generated code.
+        quote! {
+            #(#macros)*
+            macro_rules! #last {
+                ([$($args:tt)*]) => { #[allow(dead_code)] #impl_def };
+            }
+            #first! { [] }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn create_builder_and_constructor(
+    struct_visibility: &Visibility,
+    struct_name: &Ident,
+    builder_struct_name: &Ident,
+    fake_lifetime: &Ident,
+    generic_params: &Generics,
+    generic_args: &[TokenStream2],
+    field_info: &[StructFieldInfo],
+    options: &Options,
+    make_async: bool,
+) -> Result<(TokenStream2, TokenStream2), Error> {
+    let this = options.this_lifetime();
+    let visibility = options.item_visibility(&options.vis.new, struct_visibility);
+    let builder_visibility = options.item_visibility(&options.vis.builder, struct_visibility);
+    let builder_attrs = options.builder_attrs();
+    let documentation = format!(
+        concat!(
             "Constructs a new instance of this self-referential struct. (See also ",
             "[`{0}::build()`]({0}::build)). Each argument is a field of ",
             "the new struct. Fields that refer to other fields inside the struct are initialized ",
@@ -664,98 +1644,113 @@ fn create_builder_and_constructor(
     .to_owned();
     let build_fn_documentation = format!(
         concat!(
-            "Calls [`{0}::new()`]({0}::new) using the provided values. This is preferrable over ",
-            "calling `new()` directly for the reasons listed above. "
+            "Constructs the struct like [`{0}::new()`]({0}::new) using the provided values. This ",
+            "is preferrable over calling `new()` directly for the reasons listed above. "
         ),
         struct_name.to_string()
     );
     let mut doc_table = "".to_owned();
     let mut code: Vec<TokenStream2> = Vec::new();
     let mut params: Vec<TokenStream2> = Vec::new();
-    let mut builder_struct_generic_producers: Vec<_> = generic_params
-        .params
-        .iter()
-        .map(|param| quote! { #param })
-        .collect();
-    let mut builder_struct_generic_consumers = Vec::from(generic_args);
+    let mut builder_struct_generics = BuilderGenerics::new(
+        generic_params
+            .params
+            .iter()
+            .map(|param| quote! { #param })
+            .collect(),
+        Vec::from(generic_args),
+    );
     let mut builder_struct_fields = Vec::new();
     let mut builder_struct_field_names = Vec::new();
 
-    code.push(quote! { let mut result = ::core::mem::MaybeUninit::<Self>::uninit(); });
+    code.push(quote! {
+        let mut result = ::core::mem::MaybeUninit::<#struct_name <#(#generic_args),*>>::uninit();
+    });
 
     for field in field_info {
         let field_name = &field.name;
+        let cfg = field.cfg();
+        let docs = field.docs(options);
 
-        let arg_type = make_constructor_arg_type(
-            &field,
-            &field_info[..],
-            fake_lifetime,
-            do_chain_hack,
-            make_async,
-        )?;
+        let arg_type =
+            make_constructor_arg_type(field, field_info, fake_lifetime, &this, None, make_async)?;
         if let ArgType::Plain(plain_type) = arg_type {
             // No fancy builder function, we can just move the value directly into the struct.
-            params.push(quote! { #field_name: #plain_type });
-            builder_struct_fields.push(quote! { #field_name: #plain_type });
-            builder_struct_field_names.push(quote! { #field_name });
+            params.push(quote! { #cfg #field_name: #plain_type });
+            builder_struct_fields
+                .push(quote! { #docs #cfg #builder_visibility #field_name: #plain_type });
+            builder_struct_field_names.push(quote! { #cfg #field_name });
             doc_table += &format!(
                 "| `{}` | Directly pass in the value this field should contain |\n",
-                field_name.to_string()
+                field_name
             );
         } else if let ArgType::TraitBound(bound_type) = arg_type {
             // Trait bounds are much trickier. We need a special syntax to accept them in the
             // contructor, and generic parameters need to be added to the builder struct to make
             // it work.
             let builder_name = field.builder_name();
-            params.push(quote! { #builder_name : impl #bound_type });
+            params.push(quote! { #cfg #builder_name : impl #bound_type });
             // Ok so hear me out basically without this thing here my IDE thinks the rest of the
             // code is a string and it all turns green.
             {}
             doc_table += &format!(
                 "| `{}` | Use a function or closure: `(",
-                builder_name.to_string()
+                builder_name
             );
             let mut builder_args = Vec::new();
             for (index, borrow) in field.borrows.iter().enumerate() {
                 let borrowed_name = &field_info[borrow.index].name;
-                builder_args.push(format_ident!("{}_illegal_static_reference", borrowed_name));
+                let ref_name = format_ident!("{}_illegal_static_reference", borrowed_name);
+                builder_args.push(quote! { #ref_name });
                 doc_table += &format!(
                     "{}: &{}_",
-                    borrowed_name.to_string(),
+                    borrowed_name,
                     if borrow.mutable { "mut " } else { "" },
                 );
                 if index < field.borrows.len() - 1 {
                     doc_table += ", ";
                 }
             }
-            doc_table += &format!(") -> {}: _` | \n", field_name.to_string());
+            builder_args.extend(field.make_peek_references(field_info, quote! { result }));
+            for index in &field.peeks {
+                doc_table += &format!(", {}: &_", field_info[*index].name);
+            }
+            doc_table += &format!(") -> {}: _` | \n", field_name);
             if make_async {
-                code.push(quote! { let #field_name = #builder_name (#(#builder_args),*).await; });
+                code.push(quote! {
+                    #cfg let #field_name = #builder_name (#(#builder_args),*).await;
+                });
             } else {
-                code.push(quote! { let #field_name = #builder_name (#(#builder_args),*); });
+                code.push(quote! { #cfg let #field_name = #builder_name (#(#builder_args),*); });
             }
             let generic_type_name =
                 format_ident!("{}Builder_", to_class_case(field_name.to_string().as_str()));
 
-            builder_struct_generic_producers.push(quote! { #generic_type_name: #bound_type });
-            builder_struct_generic_consumers.push(quote! { #generic_type_name });
-            builder_struct_fields.push(quote! { #builder_name: #generic_type_name });
-            builder_struct_field_names.push(quote! { #builder_name });
+            builder_struct_generics.push(
+                quote! { #generic_type_name: #bound_type },
+                quote! { #generic_type_name },
+                field.cfg_predicate(),
+            );
+            builder_struct_fields
+                .push(quote! { #docs #cfg #builder_visibility #builder_name: #generic_type_name });
+            builder_struct_field_names.push(quote! { #cfg #builder_name });
         }
         let field_type = &field.typ;
-        let field_type = replace_this_with_lifetime(quote! { #field_type }, fake_lifetime.clone());
-        code.push(quote! { unsafe {
+        let field_type = replace_this_with_lifetime(quote! { #field_type }, &this, fake_lifetime.clone());
+        code.push(quote! { #cfg unsafe {
             ((&mut (*result.as_mut_ptr()).#field_name) as *mut #field_type).write(#field_name);
         }});
 
         if field.field_type == FieldType::Borrowed {
-            code.push(field.make_illegal_static_reference());
+            let illegal_reference = field.make_illegal_static_reference();
+            code.push(quote! { #cfg #illegal_reference });
         } else if field.field_type == FieldType::BorrowedMut {
-            code.push(field.make_illegal_static_mut_reference());
+            let illegal_reference = field.make_illegal_static_mut_reference();
+            code.push(quote! { #cfg #illegal_reference });
         }
     }
 
-    let documentation = if !do_no_doc {
+    let documentation = if !options.do_no_doc {
         let documentation = documentation + &doc_table;
         quote! {
             #[doc=#documentation]
@@ -764,7 +1759,7 @@ fn create_builder_and_constructor(
         quote! { #[doc(hidden)] }
     };
 
-    let builder_documentation = if !do_no_doc {
+    let builder_documentation = if !options.do_no_doc {
         let builder_documentation = builder_documentation + &doc_table;
         quote! {
             #[doc=#builder_documentation]
@@ -778,11 +1773,57 @@ fn create_builder_and_constructor(
     } else {
         quote! { fn new }
     };
+    let await_token = if make_async {
+        quote! { .await }
+    } else {
+        quote! {}
+    };
+    // Arguments cannot be conditionally compiled but struct literal fields can, so the
+    // constructors create the builder and the struct is actually assembled in build().
+    let constructor_code = quote! {
+        #builder_struct_name {
+            #(#builder_struct_field_names),*
+        }.build()#await_token
+    };
+    let from_heads = make_from_heads_parts(
+        field_info,
+        &params,
+        &builder_struct_fields,
+        &builder_struct_field_names,
+    );
+    let from_heads_params = &from_heads.params;
+    let from_heads_literal_fields = &from_heads.builder_literal_fields;
+    let from_heads_code = quote! {
+        #builder_struct_name {
+            #(#from_heads_literal_fields),*
+        }.build()#await_token
+    };
+    let from_heads_fn = if make_async {
+        quote! { async fn from_heads_async }
+    } else {
+        quote! { fn from_heads }
+    };
+    let from_heads_documentation = concat!(
+        "Like `new`, but takes all [head fields](https://docs.rs/ouroboros/latest/ouroboros/attr.self_referencing.html#definitions) ",
+        "from a `Heads` value, such as one returned by `into_heads`. The remaining arguments are ",
+        "the same as the arguments to `new`."
+    );
+    let from_heads_documentation = if !options.do_no_doc {
+        quote! { #[doc=#from_heads_documentation] }
+    } else {
+        quote! { #[doc(hidden)] }
+    };
     let constructor_def = quote! {
         #documentation
         #visibility #constructor_fn(#(#params),*) -> #struct_name <#(#generic_args),*> {
-            #(#code)*
-            unsafe { result.assume_init() }
+            #constructor_code
+        }
+        #from_heads_documentation
+        #visibility #from_heads_fn(
+            heads: Heads<#(#generic_args),*>,
+            #(#from_heads_params),*
+        ) -> #struct_name <#(#generic_args),*> {
+            #from_heads_code
         }
     };
     let generic_where = &generic_params.where_clause;
@@ -791,34 +1832,70 @@ fn create_builder_and_constructor(
     } else {
         quote! { fn build }
     };
-    let builder_code = if make_async {
-        quote! {
-            #struct_name::new_async(
-                #(self.#builder_struct_field_names),*
-            ).await
-        }
-    } else {
-        quote! {
-            #struct_name::new(
-                #(self.#builder_struct_field_names),*
-            )
-        }
-    };
+    let builder_struct_params = builder_struct_generics.params();
+    let builder_impls =
+        builder_struct_generics.make_impls(builder_struct_name, |producers, consumers| {
+            quote! {
+                impl<#(#producers),*> #builder_struct_name <#(#consumers),*> #generic_where {
+                    #[doc=#build_fn_documentation]
+                    #builder_visibility #builder_fn(self) -> #struct_name <#(#generic_args),*> {
+                        let Self { #(#builder_struct_field_names),* } = self;
+                        #(#code)*
+                        unsafe { result.assume_init() }
+                    }
+                }
+            }
+        });
     let builder_def = quote! {
         #builder_documentation
-        #visibility struct #builder_struct_name <#(#builder_struct_generic_producers),*> #generic_where {
-            #(#visibility #builder_struct_fields),*
+        #builder_attrs
+        #builder_visibility struct #builder_struct_name <#(#builder_struct_params),*> #generic_where {
+            #(#builder_struct_fields),*
         }
-        impl<#(#builder_struct_generic_producers),*> #builder_struct_name <#(#builder_struct_generic_consumers),*> #generic_where {
-            #[doc=#build_fn_documentation]
-            #visibility #builder_fn(self) -> #struct_name <#(#generic_args),*> {
-                #builder_code
+        #builder_impls
+    };
+    let heads_builder_struct_name = heads_builder_name(builder_struct_name);
+    let heads_builder_fields = &from_heads.builder_fields;
+    let heads_builder_field_names = &from_heads.builder_field_names;
+    let heads_builder_documentation = format!(
+        concat!(
+            "Like [`{0}`]({0}), but takes all ",
+            "[head fields](https://docs.rs/ouroboros/latest/ouroboros/attr.self_referencing.html#definitions) ",
+            "from a `Heads` value. Call [`build()`](Self::build) to construct the actual struct."
+        ),
+        builder_struct_name
+    );
+    let heads_build_fn_documentation = format!(
+        "Calls [`{0}::{1}()`]({0}::{1}) using the provided values.",
+        struct_name,
+        if make_async { "from_heads_async" } else { "from_heads" }
+    );
+    let heads_builder_impls =
+        builder_struct_generics.make_impls(&heads_builder_struct_name, |producers, consumers| {
+            quote! {
+                impl<#(#producers),*> #heads_builder_struct_name <#(#consumers),*> #generic_where {
+                    #[doc=#heads_build_fn_documentation]
+                    #builder_visibility #builder_fn(self) -> #struct_name <#(#generic_args),*> {
+                        let Self { heads, #(#heads_builder_field_names),* } = self;
+                        #from_heads_code
+                    }
+                }
             }
+        });
+    let builder_def = quote! {
+        #builder_def
+        #[doc=#heads_builder_documentation]
+        #builder_attrs
+        #builder_visibility struct #heads_builder_struct_name <#(#builder_struct_params),*> #generic_where {
+            #builder_visibility heads: Heads<#(#generic_args),*>,
+            #(#heads_builder_fields),*
         }
+        #heads_builder_impls
     };
     Ok((builder_def, constructor_def))
 }
 
+#[allow(clippy::too_many_arguments)]
 fn create_try_builder_and_constructor(
     struct_visibility: &Visibility,
     struct_name: &Ident,
@@ -827,21 +1904,19 @@ fn create_try_builder_and_constructor(
     generic_params: &Generics,
     generic_args: &[TokenStream2],
     field_info: &[StructFieldInfo],
-    do_chain_hack: bool,
-    do_no_doc: bool,
-    do_pub_extras: bool,
+    options: &Options,
     make_async: bool,
 ) -> Result<(TokenStream2, TokenStream2), Error> {
-    let visibility = if do_pub_extras {
-        struct_visibility.clone()
-    } else {
-        syn::parse_quote! { pub(super) }
-    };
+    let this = options.this_lifetime();
+    let visibility = options.item_visibility(&options.vis.new, struct_visibility);
+    let builder_visibility = options.item_visibility(&options.vis.builder, struct_visibility);
+    let builder_attrs = options.builder_attrs();
     let mut head_recover_code = Vec::new();
     for field in field_info {
         if !field.self_referencing {
             let field_name = &field.name;
-            head_recover_code.push(quote! { #field_name });
+            let cfg = field.cfg();
+            head_recover_code.push(quote! { #cfg #field_name });
         }
     }
     for (_ty, ident) in make_template_consumers(generic_params) {
@@ -869,67 +1944,91 @@ fn create_try_builder_and_constructor(
         ),
         builder_struct_name.to_string()
     );
-    let builder_documentation = concat!(
-        "A more verbose but stable way to construct self-referencing structs. It is ",
-        "comparable to using `StructName { field1: value1, field2: value2 }` rather than ",
-        "`StructName::new(value1, value2)`. This has the dual benefit of makin your code ",
-        "both easier to refactor and more readable. Call [`try_build()`](Self::try_build) or ",
-        "[`try_build_or_recover()`](Self::try_build_or_recover) to ",
-        "construct the actual struct. The fields of this struct should be used as follows:\n\n",
-        "| Field | Suggested Use |\n| --- | --- |\n",
-    )
-    .to_owned();
+    let builder_documentation = format!(
+        concat!(
+            "A more verbose but stable way to construct self-referencing structs. It is ",
+            "comparable to using `StructName {{ field1: value1, field2: value2 }}` rather than ",
+            "`StructName::new(value1, value2)`. This has the dual benefit of makin your code ",
+            "both easier to refactor and more readable. Call [`try_build()`](Self::try_build) ",
+            "{0}to construct the actual struct. The fields of this struct should be used as ",
+            "follows:\n\n| Field | Suggested Use |\n| --- | --- |\n",
+        ),
+        if options.do_per_field_errors {
+            ""
+        } else {
+            "or [`try_build_or_recover()`](Self::try_build_or_recover) "
+        },
+    );
     let build_fn_documentation = format!(
         concat!(
-            "Calls [`{0}::try_new()`]({0}::try_new) using the provided values. This is ",
-            "preferrable over calling `try_new()` directly for the reasons listed above. "
+            "Constructs the struct like [`{0}::try_new()`]({0}::try_new) using the provided ",
+            "values. This is preferrable over calling `try_new()` directly for the reasons listed ",
+            "above. "
         ),
         struct_name.to_string()
     );
     let build_or_recover_fn_documentation = format!(
         concat!(
-            "Calls [`{0}::try_new_or_recover()`]({0}::try_new_or_recover) using the provided ",
-            "values. This is preferrable over calling `try_new_or_recover()` directly for the ",
-            "reasons listed above. "
+            "Constructs the struct like [`{0}::try_new_or_recover()`]({0}::try_new_or_recover) ",
+            "using the provided values. This is preferrable over calling `try_new_or_recover()` ",
+            "directly for the reasons listed above. "
         ),
         struct_name.to_string()
     );
     let mut doc_table = "".to_owned();
     let mut or_recover_code: Vec<TokenStream2> = Vec::new();
     let mut params: Vec<TokenStream2> = Vec::new();
-    let mut builder_struct_generic_producers: Vec<_> = generic_params
-        .params
-        .iter()
-        .map(|param| quote! { #param })
-        .collect();
-    let mut builder_struct_generic_consumers = Vec::from(generic_args);
+    let mut builder_struct_generics = BuilderGenerics::new(
+        generic_params
+            .params
+            .iter()
+            .map(|param| quote! { #param })
+            .collect(),
+        Vec::from(generic_args),
+    );
     let mut builder_struct_fields = Vec::new();
     let mut builder_struct_field_names = Vec::new();
 
-    or_recover_code.push(quote! { let mut result = ::core::mem::MaybeUninit::<Self>::uninit(); });
+    let per_field_errors = options.do_per_field_errors;
+    let build_error_name = format_ident!("{}BuildError", struct_name);
+    let mut error_params = Vec::new();
+
+    or_recover_code.push(quote! {
+        let mut result = ::core::mem::MaybeUninit::<#struct_name <#(#generic_args),*>>::uninit();
+    });
 
     for field in field_info {
         let field_name = &field.name;
+        let cfg = field.cfg();
+        let docs = field.docs(options);
+        let error_param = if per_field_errors {
+            field.error_param_name()
+        } else {
+            format_ident!("Error_")
+        };
 
         let arg_type = make_try_constructor_arg_type(
-            &field,
-            &field_info[..],
+            field,
+            field_info,
             fake_lifetime,
-            do_chain_hack,
+            &this,
+            &error_param,
+            None,
             make_async,
         )?;
         if let ArgType::Plain(plain_type) = arg_type {
             // No fancy builder function, we can just move the value directly into the struct.
-            params.push(quote! { #field_name: #plain_type });
-            builder_struct_fields.push(quote! { #field_name: #plain_type });
-            builder_struct_field_names.push(quote! { #field_name });
+            params.push(quote! { #cfg #field_name: #plain_type });
+            builder_struct_fields
+                .push(quote! { #docs #cfg #builder_visibility #field_name: #plain_type });
+            builder_struct_field_names.push(quote! { #cfg #field_name });
             doc_table += &format!(
                 "| `{}` | Directly pass in the value this field should contain |\n",
-                field_name.to_string()
+                field_name
             );
             if !field.self_referencing {
                 head_recover_code[current_head_index] = quote! {
-                    #field_name: unsafe { ::core::ptr::read(&(*result.as_ptr()).#field_name as *const _) }
+                    #cfg #field_name: unsafe { ::core::ptr::read(&(*result.as_ptr()).#field_name as *const _) }
                 };
                 current_head_index += 1;
             }
@@ -938,62 +2037,100 @@ fn create_try_builder_and_constructor(
             // contructor, and generic parameters need to be added to the builder struct to make
             // it work.
             let builder_name = field.builder_name();
-            params.push(quote! { #builder_name : impl #bound_type });
+            params.push(quote! { #cfg #builder_name : impl #bound_type });
             // Ok so hear me out basically without this thing here my IDE thinks the rest of the
             // code is a string and it all turns green.
             {}
             doc_table += &format!(
                 "| `{}` | Use a function or closure: `(",
-                builder_name.to_string()
+                builder_name
             );
             let mut builder_args = Vec::new();
             for (index, borrow) in field.borrows.iter().enumerate() {
                 let borrowed_name = &field_info[borrow.index].name;
-                builder_args.push(format_ident!("{}_illegal_static_reference", borrowed_name));
+                let ref_name = format_ident!("{}_illegal_static_reference", borrowed_name);
+                builder_args.push(quote! { #ref_name });
                 doc_table += &format!(
                     "{}: &{}_",
-                    borrowed_name.to_string(),
+                    borrowed_name,
                     if borrow.mutable { "mut " } else { "" },
                 );
                 if index < field.borrows.len() - 1 {
                     doc_table += ", ";
                 }
             }
-            doc_table += &format!(") -> Result<{}: _, Error_>` | \n", field_name.to_string());
+            builder_args.extend(field.make_peek_references(field_info, quote! { result }));
+            for index in &field.peeks {
+                doc_table += &format!(", {}: &_", field_info[*index].name);
+            }
             let builder_value = if make_async {
                 quote! { #builder_name (#(#builder_args),*).await }
             } else {
                 quote! { #builder_name (#(#builder_args),*) }
             };
-            or_recover_code.push(quote! {
-                let #field_name = match #builder_value {
-                    ::core::result::Result::Ok(value) => value,
-                    ::core::result::Result::Err(err)
-                        => return ::core::result::Result::Err((err, Heads { #(#head_recover_code),* })),
+            if field.infallible {
+                doc_table += &format!(") -> {}: _` | \n", field_name);
+                or_recover_code.push(quote! { #cfg let #field_name = #builder_value; });
+            } else {
+                if per_field_errors && !field.cfg_attrs.is_empty() {
+                    return Err(Error::new_spanned(
+                        &field.name,
+                        concat!(
+                            "per_field_errors cannot be used with fallible builders for fields ",
+                            "marked #[cfg()], since variants of the error enum cannot depend on ",
+                            "them. Mark the field #[infallible] or remove per_field_errors."
+                        ),
+                    ));
+                }
+                doc_table += &format!(
+                    ") -> Result<{}: _, {}>` | \n",
+                    field_name, error_param
+                );
+                let error_value = if per_field_errors {
+                    let variant = field.error_variant_name();
+                    quote! { #build_error_name::#variant(err, Heads { #(#head_recover_code),* }) }
+                } else {
+                    quote! { (err, Heads { #(#head_recover_code),* }) }
                 };
-            });
+                or_recover_code.push(quote! {
+                    #cfg let #field_name = match #builder_value {
+                        ::core::result::Result::Ok(value) => value,
+                        ::core::result::Result::Err(err)
+                            => return ::core::result::Result::Err(#error_value),
+                    };
+                });
+                if per_field_errors {
+                    error_params.push(error_param);
+                }
+            }
             let generic_type_name =
                 format_ident!("{}Builder_", to_class_case(field_name.to_string().as_str()));
 
-            builder_struct_generic_producers.push(quote! { #generic_type_name: #bound_type });
-            builder_struct_generic_consumers.push(quote! { #generic_type_name });
-            builder_struct_fields.push(quote! { #builder_name: #generic_type_name });
-            builder_struct_field_names.push(quote! { #builder_name });
+            builder_struct_generics.push(
+                quote! { #generic_type_name: #bound_type },
+                quote! { #generic_type_name },
+                field.cfg_predicate(),
+            );
+            builder_struct_fields
+                .push(quote! { #docs #cfg #builder_visibility #builder_name: #generic_type_name });
+            builder_struct_field_names.push(quote! { #cfg #builder_name });
         }
         let field_type = &field.typ;
-        let field_type = replace_this_with_lifetime(quote! { #field_type }, fake_lifetime.clone());
-        let line = quote! { unsafe {
+        let field_type = replace_this_with_lifetime(quote! { #field_type }, &this, fake_lifetime.clone());
+        let line = quote! { #cfg unsafe {
             ((&mut (*result.as_mut_ptr()).#field_name) as *mut #field_type).write(#field_name);
         }};
         or_recover_code.push(line);
 
         if field.field_type == FieldType::Borrowed {
-            or_recover_code.push(field.make_illegal_static_reference());
+            let illegal_reference = field.make_illegal_static_reference();
+            or_recover_code.push(quote! { #cfg #illegal_reference });
         } else if field.field_type == FieldType::BorrowedMut {
-            or_recover_code.push(field.make_illegal_static_mut_reference());
+            let illegal_reference = field.make_illegal_static_mut_reference();
+            or_recover_code.push(quote! { #cfg #illegal_reference });
         }
     }
-    let documentation = if !do_no_doc {
+    let documentation = if !options.do_no_doc {
         let documentation = documentation + &doc_table;
         quote! {
             #[doc=#documentation]
@@ -1001,7 +2138,7 @@ fn create_try_builder_and_constructor(
     } else {
         quote! { #[doc(hidden)] }
     };
-    let or_recover_documentation = if !do_no_doc {
+    let or_recover_documentation = if !options.do_no_doc {
         let or_recover_documentation = or_recover_documentation + &doc_table;
         quote! {
             #[doc=#or_recover_documentation]
@@ -1009,7 +2146,7 @@ fn create_try_builder_and_constructor(
     } else {
         quote! { #[doc(hidden)] }
     };
-    let builder_documentation = if !do_no_doc {
+    let builder_documentation = if !options.do_no_doc {
         let builder_documentation = builder_documentation + &doc_table;
         quote! {
             #[doc=#builder_documentation]
@@ -1017,39 +2154,131 @@ fn create_try_builder_and_constructor(
     } else {
         quote! { #[doc(hidden)] }
     };
-    let or_recover_ident = if make_async {
-        quote! { try_new_or_recover_async }
-    } else {
-        quote! { try_new_or_recover }
-    };
     let or_recover_constructor_fn = if make_async {
-        quote! { async fn #or_recover_ident }
+        quote! { async fn try_new_or_recover_async }
     } else {
-        quote! { fn #or_recover_ident }
+        quote! { fn try_new_or_recover }
     };
     let constructor_fn = if make_async {
         quote! { async fn try_new_async }
     } else {
         quote! { fn try_new }
     };
-    let constructor_code = if make_async {
-        quote! { #struct_name::#or_recover_ident(#(#builder_struct_field_names),*).await.map_err(|(error, _heads)| error) }
+    let await_token = if make_async {
+        quote! { .await }
     } else {
-        quote! { #struct_name::#or_recover_ident(#(#builder_struct_field_names),*).map_err(|(error, _heads)| error) }
+        quote! {}
     };
-    let constructor_def = quote! {
-        #documentation
-        #visibility #constructor_fn<Error_>(#(#params),*) -> ::core::result::Result<#struct_name <#(#generic_args),*>, Error_> {
-            #constructor_code
+    // With per_field_errors the heads are part of the error enum, so there is no need for
+    // separate _or_recover variants.
+    let (error_params, error_type) = if per_field_errors {
+        let error_type = quote! { #build_error_name<#(#generic_args,)* #(#error_params),*> };
+        (error_params, error_type)
+    } else {
+        (vec![format_ident!("Error_")], quote! { Error_ })
+    };
+    // Like the infallible constructors, these create the builder and the struct is actually
+    // assembled in try_build() or try_build_or_recover().
+    let builder_literal = quote! {
+        #builder_struct_name {
+            #(#builder_struct_field_names),*
+        }
+    };
+    let constructor_def = if per_field_errors {
+        quote! {
+            #documentation
+            #visibility #constructor_fn<#(#error_params),*>(#(#params),*) -> ::core::result::Result<#struct_name <#(#generic_args),*>, #error_type> {
+                #builder_literal.try_build()#await_token
+            }
+        }
+    } else {
+        quote! {
+            #documentation
+            #visibility #constructor_fn<Error_>(#(#params),*) -> ::core::result::Result<#struct_name <#(#generic_args),*>, Error_> {
+                #builder_literal.try_build()#await_token
+            }
+            #or_recover_documentation
+            #visibility #or_recover_constructor_fn<Error_>(#(#params),*) -> ::core::result::Result<#struct_name <#(#generic_args),*>, (Error_, Heads<#(#generic_args),*>)> {
+                #builder_literal.try_build_or_recover()#await_token
+            }
+        }
+    };
+    let from_heads = make_from_heads_parts(
+        field_info,
+        &params,
+        &builder_struct_fields,
+        &builder_struct_field_names,
+    );
+    let from_heads_params = &from_heads.params;
+    let from_heads_literal_fields = &from_heads.builder_literal_fields;
+    let from_heads_literal = quote! {
+        #builder_struct_name {
+            #(#from_heads_literal_fields),*
         }
-        #or_recover_documentation
-        #visibility #or_recover_constructor_fn<Error_>(#(#params),*) -> ::core::result::Result<#struct_name <#(#generic_args),*>, (Error_, Heads<#(#generic_args),*>)> {
-            #(#or_recover_code)*
-            ::core::result::Result::Ok(unsafe { result.assume_init() })
+    };
+    let (from_heads_ident, from_heads_or_recover_ident) = if make_async {
+        (
+            quote! { try_from_heads_async },
+            quote! { try_from_heads_or_recover_async },
+        )
+    } else {
+        (quote! { try_from_heads }, quote! { try_from_heads_or_recover })
+    };
+    let (from_heads_fn, from_heads_or_recover_fn) = if make_async {
+        (
+            quote! { async fn #from_heads_ident },
+            quote! { async fn #from_heads_or_recover_ident },
+        )
+    } else {
+        (
+            quote! { fn #from_heads_ident },
+            quote! { fn #from_heads_or_recover_ident },
+        )
+    };
+    let from_heads_documentation = concat!(
+        "Like `try_new`, but takes all [head fields](https://docs.rs/ouroboros/latest/ouroboros/attr.self_referencing.html#definitions) ",
+        "from a `Heads` value, such as one returned by `into_heads` or `try_new_or_recover`. The ",
+        "remaining arguments are the same as the arguments to `try_new`."
+    );
+    let from_heads_or_recover_documentation = concat!(
+        "Like `try_from_heads`, but the heads are returned in the case of an error so that ",
+        "construction can be retried."
+    );
+    let (from_heads_documentation, from_heads_or_recover_documentation) = if !options.do_no_doc {
+        (
+            quote! { #[doc=#from_heads_documentation] },
+            quote! { #[doc=#from_heads_or_recover_documentation] },
+        )
+    } else {
+        (quote! { #[doc(hidden)] }, quote! { #[doc(hidden)] })
+    };
+    let from_heads_or_recover_def = if per_field_errors {
+        quote! {}
+    } else {
+        quote! {
+            #from_heads_or_recover_documentation
+            #visibility #from_heads_or_recover_fn<Error_>(
+                heads: Heads<#(#generic_args),*>,
+                #(#from_heads_params),*
+            ) -> ::core::result::Result<#struct_name <#(#generic_args),*>, (Error_, Heads<#(#generic_args),*>)> {
+                #from_heads_literal.try_build_or_recover()#await_token
+            }
         }
     };
-    builder_struct_generic_producers.push(quote! { Error_ });
-    builder_struct_generic_consumers.push(quote! { Error_ });
+    let constructor_def = quote! {
+        #constructor_def
+        #from_heads_documentation
+        #visibility #from_heads_fn<#(#error_params),*>(
+            heads: Heads<#(#generic_args),*>,
+            #(#from_heads_params),*
+        ) -> ::core::result::Result<#struct_name <#(#generic_args),*>, #error_type> {
+            #from_heads_literal.try_build()#await_token
+        }
+        #from_heads_or_recover_def
+    };
+    for error_param in &error_params {
+        builder_struct_generics.push(quote! { #error_param }, quote! { #error_param }, None);
+    }
     let generic_where = &generic_params.where_clause;
     let builder_fn = if make_async {
         quote! { async fn try_build }
@@ -1061,99 +2290,1020 @@ fn create_try_builder_and_constructor(
     } else {
         quote! { fn try_build_or_recover }
     };
-    let builder_code = if make_async {
-        quote! {
-            #struct_name::try_new_async(
-                #(self.#builder_struct_field_names),*
-            ).await
-        }
-    } else {
-        quote! {
-            #struct_name::try_new(
-                #(self.#builder_struct_field_names),*
-            )
+    let builder_struct_params = builder_struct_generics.params();
+    let builder_impls =
+        builder_struct_generics.make_impls(builder_struct_name, |producers, consumers| {
+            let build_fns = if per_field_errors {
+                quote! {
+                    #[doc=#build_fn_documentation]
+                    #builder_visibility #builder_fn(self) -> ::core::result::Result<#struct_name <#(#generic_args),*>, #error_type> {
+                        let Self { #(#builder_struct_field_names),* } = self;
+                        #(#or_recover_code)*
+                        ::core::result::Result::Ok(unsafe { result.assume_init() })
+                    }
+                }
+            } else {
+                quote! {
+                    #[doc=#build_fn_documentation]
+                    #builder_visibility #builder_fn(self) -> ::core::result::Result<#struct_name <#(#generic_args),*>, #error_type> {
+                        self.try_build_or_recover()#await_token.map_err(|(error, _heads)| error)
+                    }
+                    #[doc=#build_or_recover_fn_documentation]
+                    #builder_visibility #or_recover_builder_fn(self) -> ::core::result::Result<#struct_name <#(#generic_args),*>, (Error_, Heads<#(#generic_args),*>)> {
+                        let Self { #(#builder_struct_field_names),* } = self;
+                        #(#or_recover_code)*
+                        ::core::result::Result::Ok(unsafe { result.assume_init() })
+                    }
+                }
+            };
+            quote! {
+                impl<#(#producers),*> #builder_struct_name <#(#consumers),*> #generic_where {
+                    #build_fns
+                }
+            }
+        });
+    let builder_def = quote! {
+        #builder_documentation
+        #builder_attrs
+        #builder_visibility struct #builder_struct_name <#(#builder_struct_params),*> #generic_where {
+            #(#builder_struct_fields),*
         }
+        #builder_impls
     };
-    let or_recover_builder_code = if make_async {
-        quote! {
-            #struct_name::try_new_or_recover_async(
-                #(self.#builder_struct_field_names),*
-            ).await
-        }
+    let heads_builder_struct_name = heads_builder_name(builder_struct_name);
+    let heads_builder_fields = &from_heads.builder_fields;
+    let heads_builder_field_names = &from_heads.builder_field_names;
+    let heads_builder_documentation = format!(
+        concat!(
+            "Like [`{0}`]({0}), but takes all ",
+            "[head fields](https://docs.rs/ouroboros/latest/ouroboros/attr.self_referencing.html#definitions) ",
+            "from a `Heads` value. Call [`try_build()`](Self::try_build) to construct the actual ",
+            "struct."
+        ),
+        builder_struct_name
+    );
+    let heads_build_fn_documentation = format!(
+        "Calls [`{0}::{1}()`]({0}::{1}) using the provided values.",
+        struct_name, from_heads_ident
+    );
+    let heads_build_or_recover_fn_documentation = format!(
+        "Calls [`{0}::{1}()`]({0}::{1}) using the provided values.",
+        struct_name, from_heads_or_recover_ident
+    );
+    let heads_or_recover_builder_def = if per_field_errors {
+        quote! {}
     } else {
         quote! {
-            #struct_name::try_new_or_recover(
-                #(self.#builder_struct_field_names),*
-            )
+            #[doc=#heads_build_or_recover_fn_documentation]
+            #builder_visibility #or_recover_builder_fn(self) -> ::core::result::Result<#struct_name <#(#generic_args),*>, (Error_, Heads<#(#generic_args),*>)> {
+                let Self { heads, #(#heads_builder_field_names),* } = self;
+                #from_heads_literal.try_build_or_recover()#await_token
+            }
         }
     };
-    let builder_def = quote! {
-        #builder_documentation
-        #visibility struct #builder_struct_name <#(#builder_struct_generic_producers),*> #generic_where {
-            #(#visibility #builder_struct_fields),*
-        }
-        impl<#(#builder_struct_generic_producers),*> #builder_struct_name <#(#builder_struct_generic_consumers),*> #generic_where {
-            #[doc=#build_fn_documentation]
-            #visibility #builder_fn(self) -> ::core::result::Result<#struct_name <#(#generic_args),*>, Error_> {
-                #builder_code
-            }
-            #[doc=#build_or_recover_fn_documentation]
-            #visibility #or_recover_builder_fn(self) -> ::core::result::Result<#struct_name <#(#generic_args),*>, (Error_, Heads<#(#generic_args),*>)> {
-                #or_recover_builder_code
+    let heads_builder_impls =
+        builder_struct_generics.make_impls(&heads_builder_struct_name, |producers, consumers| {
+            quote! {
+                impl<#(#producers),*> #heads_builder_struct_name <#(#consumers),*> #generic_where {
+                    #[doc=#heads_build_fn_documentation]
+                    #builder_visibility #builder_fn(self) -> ::core::result::Result<#struct_name <#(#generic_args),*>, #error_type> {
+                        let Self { heads, #(#heads_builder_field_names),* } = self;
+                        #from_heads_literal.try_build()#await_token
+                    }
+                    #heads_or_recover_builder_def
+                }
             }
+        });
+    let builder_def = quote! {
+        #builder_def
+        #[doc=#heads_builder_documentation]
+        #builder_attrs
+        #builder_visibility struct #heads_builder_struct_name <#(#builder_struct_params),*> #generic_where {
+            #builder_visibility heads: Heads<#(#generic_args),*>,
+            #(#heads_builder_fields),*
         }
+        #heads_builder_impls
     };
     Ok((builder_def, constructor_def))
 }
 
-fn make_with_functions(
+/// Generates `new_with_context`, `try_new_with_context` and `try_new_or_recover_with_context`
+/// along with builders for them. Each builder function receives a reference to a context value
+/// before the fields it borrows. These forward to the regular builders, wrapping every builder
+/// function in a closure which passes the context along. Nothing is generated unless the context
+/// option is used.
+fn create_context_builders_and_constructors(
+    struct_visibility: &Visibility,
+    struct_name: &Ident,
+    fake_lifetime: &Ident,
+    generic_params: &Generics,
+    generic_args: &[TokenStream2],
     field_info: &[StructFieldInfo],
-    do_no_doc: bool,
-) -> Result<Vec<TokenStream2>, Error> {
-    let mut users = Vec::new();
+    options: &Options,
+) -> Result<(TokenStream2, TokenStream2), Error> {
+    let this = options.this_lifetime();
+    let visibility = options.item_visibility(&options.vis.new, struct_visibility);
+    let builder_visibility = options.item_visibility(&options.vis.builder, struct_visibility);
+    let builder_attrs = options.builder_attrs();
+    let builder_struct_name = format_ident!("{}BuilderWithContext", struct_name);
+    let try_builder_struct_name = format_ident!("{}TryBuilderWithContext", struct_name);
+    let context_type = format_ident!("Context_");
+    let per_field_errors = options.do_per_field_errors;
+    let build_error_name = format_ident!("{}BuildError", struct_name);
+
+    let mut params = Vec::new();
+    let mut try_params = Vec::new();
+    let mut args = Vec::new();
+    let mut builder_struct_generic_producers = vec![quote! { 'context_ }];
+    builder_struct_generic_producers.extend(generic_params.params.iter().map(|p| quote! { #p }));
+    builder_struct_generic_producers
+        .push(quote! { #context_type: ?::core::marker::Sized + 'context_ });
+    let mut builder_struct_generic_consumers = vec![quote! { 'context_ }];
+    builder_struct_generic_consumers.extend(generic_args.iter().cloned());
+    builder_struct_generic_consumers.push(quote! { #context_type });
+    let mut builder_struct_generics = BuilderGenerics::new(
+        builder_struct_generic_producers,
+        builder_struct_generic_consumers,
+    );
+    let mut try_builder_struct_generics = BuilderGenerics::new(
+        builder_struct_generics.producers.clone(),
+        builder_struct_generics.consumers.clone(),
+    );
+    let mut builder_struct_fields = Vec::new();
+    let mut try_builder_struct_fields = Vec::new();
+    let mut builder_struct_field_names = Vec::new();
+    let mut doc_table = "".to_owned();
+    let mut try_doc_table = "".to_owned();
+    let mut error_params = Vec::new();
+
     for field in field_info {
-        let visibility = &field.vis;
         let field_name = &field.name;
-        let field_type = &field.typ;
-        // If the field is not a tail, we need to serve up the same kind of reference that other
-        // fields in the struct may have borrowed to ensure safety.
-        if field.field_type == FieldType::Tail {
-            let user_name = format_ident!("with_{}", &field.name);
-            let documentation = format!(
-                concat!(
-                    "Provides an immutable reference to `{0}`. This method was generated because ",
-                    "`{0}` is a [tail field](https://docs.rs/ouroboros/latest/ouroboros/attr.self_referencing.html#definitions)."
-                ),
-                field.name.to_string()
-            );
-            let documentation = if !do_no_doc {
-                quote! {
-                    #[doc=#documentation]
+        let cfg = field.cfg();
+        let docs = field.docs(options);
+        let error_param = if per_field_errors {
+            field.error_param_name()
+        } else {
+            format_ident!("Error_")
+        };
+        let arg_type = make_constructor_arg_type(
+            field,
+            field_info,
+            fake_lifetime,
+            &this,
+            Some(&context_type),
+            false,
+        )?;
+        let try_arg_type = make_try_constructor_arg_type(
+            field,
+            field_info,
+            fake_lifetime,
+            &this,
+            &error_param,
+            Some(&context_type),
+            false,
+        )?;
+        match (arg_type, try_arg_type) {
+            (ArgType::Plain(plain_type), _) | (_, ArgType::Plain(plain_type)) => {
+                params.push(quote! { #cfg #field_name: #plain_type });
+                try_params.push(quote! { #cfg #field_name: #plain_type });
+                args.push(quote! { #cfg #field_name });
+                builder_struct_fields
+                    .push(quote! { #docs #cfg #builder_visibility #field_name: #plain_type });
+                try_builder_struct_fields
+                    .push(quote! { #docs #cfg #builder_visibility #field_name: #plain_type });
+                builder_struct_field_names.push(quote! { #cfg #field_name });
+                let row = format!(
+                    "| `{}` | Directly pass in the value this field should contain |\n",
+                    field_name
+                );
+                doc_table += &row;
+                try_doc_table += &row;
+            }
+            (ArgType::TraitBound(bound_type), ArgType::TraitBound(try_bound_type)) => {
+                let builder_name = field.builder_name();
+                params.push(quote! { #cfg #builder_name: impl #bound_type });
+                try_params.push(quote! { #cfg #builder_name: impl #try_bound_type });
+                let arg_names: Vec<_> = field
+                    .borrows
+                    .iter()
+                    .map(|borrow| field_info[borrow.index].name.clone())
+                    .chain(
+                        field
+                            .peeks
+                            .iter()
+                            .map(|index| format_ident!("peeked_{}", field_info[*index].name)),
+                    )
+                    .collect();
+                args.push(quote! {
+                    #cfg #builder_name: |#(#arg_names),*| #builder_name(context_, #(#arg_names),*)
+                });
+                let mut row = format!(
+                    "| `{}` | Use a function or closure: `(context_: &Context_",
+                    builder_name
+                );
+                for borrow in &field.borrows {
+                    row += &format!(
+                        ", {}: &{}_",
+                        field_info[borrow.index].name,
+                        if borrow.mutable { "mut " } else { "" },
+                    );
                 }
-            } else {
-                quote! { #[doc(hidden)] }
-            };
-            users.push(quote! {
-                #documentation
-                #visibility fn #user_name <'outer_borrow, ReturnType>(
-                    &'outer_borrow self,
-                    user: impl for<'this> ::core::ops::FnOnce(&'outer_borrow #field_type) -> ReturnType,
-                ) -> ReturnType {
-                    user(&self. #field_name)
+                for index in &field.peeks {
+                    row += &format!(", {}: &_", field_info[*index].name);
                 }
-            });
-            if field.covariant == Some(true) {
-                let borrower_name = format_ident!("borrow_{}", &field.name);
-                users.push(quote! {
-                    #documentation
-                    #visibility fn #borrower_name<'this>(
-                        &'this self,
-                    ) -> &'this #field_type {
-                        &self.#field_name
+                doc_table += &format!("{}) -> {}: _` | \n", row, field_name);
+                if field.infallible {
+                    try_doc_table += &format!("{}) -> {}: _` | \n", row, field_name);
+                } else {
+                    try_doc_table += &format!(
+                        "{}) -> Result<{}: _, {}>` | \n",
+                        row, field_name, error_param
+                    );
+                    if per_field_errors {
+                        error_params.push(error_param);
                     }
-                });
-            } else if field.covariant.is_none() {
-                field.covariance_error();
+                }
+                let generic_type_name =
+                    format_ident!("{}Builder_", to_class_case(field_name.to_string().as_str()));
+                builder_struct_generics.push(
+                    quote! { #generic_type_name: #bound_type },
+                    quote! { #generic_type_name },
+                    field.cfg_predicate(),
+                );
+                try_builder_struct_generics.push(
+                    quote! { #generic_type_name: #try_bound_type },
+                    quote! { #generic_type_name },
+                    field.cfg_predicate(),
+                );
+                builder_struct_fields.push(quote! {
+                    #docs #cfg #builder_visibility #builder_name: #generic_type_name
+                });
+                try_builder_struct_fields.push(quote! {
+                    #docs #cfg #builder_visibility #builder_name: #generic_type_name
+                });
+                builder_struct_field_names.push(quote! { #cfg #builder_name });
+            }
+        }
+    }
+    let (error_params, error_type) = if per_field_errors {
+        let error_type = quote! { #build_error_name<#(#generic_args,)* #(#error_params),*> };
+        (error_params, error_type)
+    } else {
+        (vec![format_ident!("Error_")], quote! { Error_ })
+    };
+    for error_param in &error_params {
+        try_builder_struct_generics.push(quote! { #error_param }, quote! { #error_param }, None);
+    }
+
+    let documentation = format!(
+        concat!(
+            "(See also [`{0}::build()`]({0}::build).) Like [`new`](Self::new), but every ",
+            "builder function is also given `context_`. This is useful when builders need access ",
+            "to something that is not stored in the struct, such as a shared configuration or an ",
+            "allocator. The arguments are as follows:\n\n",
+            "| Argument | Suggested Use |\n| --- | --- |\n",
+            "| `context_` | A reference passed to every builder |\n{1}",
+        ),
+        builder_struct_name, doc_table
+    );
+    let try_documentation = format!(
+        concat!(
+            "(See also [`{0}::try_build()`]({0}::try_build).) Like ",
+            "[`try_new`](Self::try_new), but every builder function is also given `context_`. ",
+            "The arguments are as follows:\n\n",
+            "| Argument | Suggested Use |\n| --- | --- |\n",
+            "| `context_` | A reference passed to every builder |\n{1}",
+        ),
+        try_builder_struct_name, try_doc_table
+    );
+    let or_recover_documentation = format!(
+        concat!(
+            "(See also [`{0}::try_build_or_recover()`]({0}::try_build_or_recover).) Like ",
+            "[`try_new_or_recover`](Self::try_new_or_recover), but every builder function is ",
+            "also given `context_`. The arguments are the same as the arguments to ",
+            "[`try_new_with_context`](Self::try_new_with_context)."
+        ),
+        try_builder_struct_name
+    );
+    let builder_documentation = format!(
+        concat!(
+            "Like [`{0}Builder`]({0}Builder), but every builder function is also given a ",
+            "reference to `context_`. Call [`build()`](Self::build) to construct the actual ",
+            "struct. The fields of this struct should be used as follows:\n\n",
+            "| Field | Suggested Use |\n| --- | --- |\n",
+            "| `context_` | A reference passed to every builder |\n{1}",
+        ),
+        struct_name, doc_table
+    );
+    let try_builder_documentation = format!(
+        concat!(
+            "Like [`{0}TryBuilder`]({0}TryBuilder), but every builder function is also given a ",
+            "reference to `context_`. Call [`try_build()`](Self::try_build) {1}to construct the ",
+            "actual struct. The fields of this struct should be used as follows:\n\n",
+            "| Field | Suggested Use |\n| --- | --- |\n",
+            "| `context_` | A reference passed to every builder |\n{2}",
+        ),
+        struct_name,
+        if per_field_errors {
+            ""
+        } else {
+            "or [`try_build_or_recover()`](Self::try_build_or_recover) "
+        },
+        try_doc_table
+    );
+    let build_fn_documentation = format!(
+        concat!(
+            "Constructs the struct like [`{0}::new_with_context()`]({0}::new_with_context) using ",
+            "the provided values."
+        ),
+        struct_name
+    );
+    let try_build_fn_documentation = format!(
+        concat!(
+            "Constructs the struct like [`{0}::try_new_with_context()`]",
+            "({0}::try_new_with_context) using the provided values."
+        ),
+        struct_name
+    );
+    let try_build_or_recover_fn_documentation = format!(
+        concat!(
+            "Constructs the struct like [`{0}::try_new_or_recover_with_context()`]",
+            "({0}::try_new_or_recover_with_context) using the provided values."
+        ),
+        struct_name
+    );
+    let (documentation, try_documentation, or_recover_documentation) = if !options.do_no_doc {
+        (
+            quote! { #[doc=#documentation] },
+            quote! { #[doc=#try_documentation] },
+            quote! { #[doc=#or_recover_documentation] },
+        )
+    } else {
+        (
+            quote! { #[doc(hidden)] },
+            quote! { #[doc(hidden)] },
+            quote! { #[doc(hidden)] },
+        )
+    };
+    let (builder_documentation, try_builder_documentation) = if !options.do_no_doc {
+        (
+            quote! { #[doc=#builder_documentation] },
+            quote! { #[doc=#try_builder_documentation] },
+        )
+    } else {
+        (quote! { #[doc(hidden)] }, quote! { #[doc(hidden)] })
+    };
+
+    // The regular builders are created with struct literals rather than by calling the regular
+    // constructors, since only struct literal fields can be conditionally compiled.
+    let regular_builder_literal = {
+        let name = format_ident!("{}Builder", struct_name);
+        quote! { #name { #(#args),* } }
+    };
+    let regular_try_builder_literal = {
+        let name = format_ident!("{}TryBuilder", struct_name);
+        quote! { #name { #(#args),* } }
+    };
+    // With per_field_errors the heads are part of the error enum, so there is no need for
+    // separate _or_recover variants.
+    let (or_recover_constructor_def, or_recover_builder_def) = if per_field_errors {
+        (quote! {}, quote! {})
+    } else {
+        (
+            quote! {
+                #or_recover_documentation
+                #visibility fn try_new_or_recover_with_context<#context_type: ?::core::marker::Sized, Error_>(
+                    context_: &#context_type,
+                    #(#try_params),*
+                ) -> ::core::result::Result<#struct_name <#(#generic_args),*>, (Error_, Heads<#(#generic_args),*>)> {
+                    #regular_try_builder_literal.try_build_or_recover()
+                }
+            },
+            quote! {
+                #[doc=#try_build_or_recover_fn_documentation]
+                #builder_visibility fn try_build_or_recover(self) -> ::core::result::Result<#struct_name <#(#generic_args),*>, (Error_, Heads<#(#generic_args),*>)> {
+                    let Self { context_, #(#builder_struct_field_names),* } = self;
+                    #regular_try_builder_literal.try_build_or_recover()
+                }
+            },
+        )
+    };
+    let constructor_def = quote! {
+        #documentation
+        #visibility fn new_with_context<#context_type: ?::core::marker::Sized>(
+            context_: &#context_type,
+            #(#params),*
+        ) -> #struct_name <#(#generic_args),*> {
+            #regular_builder_literal.build()
+        }
+        #try_documentation
+        #visibility fn try_new_with_context<#context_type: ?::core::marker::Sized, #(#error_params),*>(
+            context_: &#context_type,
+            #(#try_params),*
+        ) -> ::core::result::Result<#struct_name <#(#generic_args),*>, #error_type> {
+            #regular_try_builder_literal.try_build()
+        }
+        #or_recover_constructor_def
+    };
+    let generic_where = &generic_params.where_clause;
+    let builder_struct_params = builder_struct_generics.params();
+    let builder_impls =
+        builder_struct_generics.make_impls(&builder_struct_name, |producers, consumers| {
+            quote! {
+                impl<#(#producers),*> #builder_struct_name <#(#consumers),*> #generic_where {
+                    #[doc=#build_fn_documentation]
+                    #builder_visibility fn build(self) -> #struct_name <#(#generic_args),*> {
+                        let Self { context_, #(#builder_struct_field_names),* } = self;
+                        #regular_builder_literal.build()
+                    }
+                }
+            }
+        });
+    let try_builder_struct_params = try_builder_struct_generics.params();
+    let try_builder_impls =
+        try_builder_struct_generics.make_impls(&try_builder_struct_name, |producers, consumers| {
+            quote! {
+                impl<#(#producers),*> #try_builder_struct_name <#(#consumers),*> #generic_where {
+                    #[doc=#try_build_fn_documentation]
+                    #builder_visibility fn try_build(self) -> ::core::result::Result<#struct_name <#(#generic_args),*>, #error_type> {
+                        let Self { context_, #(#builder_struct_field_names),* } = self;
+                        #regular_try_builder_literal.try_build()
+                    }
+                    #or_recover_builder_def
+                }
+            }
+        });
+    let builder_def = quote! {
+        #builder_documentation
+        #builder_attrs
+        #builder_visibility struct #builder_struct_name <#(#builder_struct_params),*> #generic_where {
+            #builder_visibility context_: &'context_ #context_type,
+            #(#builder_struct_fields),*
+        }
+        #builder_impls
+        #try_builder_documentation
+        #builder_attrs
+        #builder_visibility struct #try_builder_struct_name <#(#try_builder_struct_params),*> #generic_where {
+            #builder_visibility context_: &'context_ #context_type,
+            #(#try_builder_struct_fields),*
+        }
+        #try_builder_impls
+    };
+    Ok((builder_def, constructor_def))
+}
+
+/// Returns the value passed to `new()` for a field marked #[default]. For fields which use a
+/// builder, `#[default = expr]` should be a builder function and `#[default]` creates a builder
+/// which ignores its arguments.
+fn default_arg(field: &StructFieldInfo) -> Option<TokenStream2> {
+    let default = field.default.as_ref()?;
+    Some(match default {
+        Some(expr) => quote! { #expr },
+        None if field.borrows.is_empty() => quote! { ::core::default::Default::default() },
+        None => {
+            let arg_count = field.borrows.len() + field.peeks.len();
+            let ignored_args = (0..arg_count).map(|_| quote! { _ });
+            quote! { |#(#ignored_args),*| ::core::default::Default::default() }
+        }
+    })
+}
+
+/// Generates `MyStructDefaultBuilder`, which omits every field marked #[default], and a `Default`
+/// implementation if every field has a default. Nothing is generated if no field has a default.
+fn make_default_builder(
+    struct_visibility: &Visibility,
+    struct_name: &Ident,
+    fake_lifetime: &Ident,
+    generic_params: &Generics,
+    generic_args: &[TokenStream2],
+    field_info: &[StructFieldInfo],
+    options: &Options,
+) -> Result<(TokenStream2, TokenStream2), Error> {
+    let this = options.this_lifetime();
+    if field_info.iter().all(|field| field.default.is_none()) {
+        return Ok((quote! {}, quote! {}));
+    }
+    let visibility = options.item_visibility(&options.vis.builder, struct_visibility);
+    let builder_attrs = options.builder_attrs();
+    let builder_struct_name = format_ident!("{}DefaultBuilder", struct_name);
+    let mut doc_table = "".to_owned();
+    let mut args = Vec::new();
+    let mut builder_struct_generics = BuilderGenerics::new(
+        generic_params
+            .params
+            .iter()
+            .map(|param| quote! { #param })
+            .collect(),
+        Vec::from(generic_args),
+    );
+    let mut builder_struct_fields = Vec::new();
+    for field in field_info {
+        let field_name = &field.name;
+        let cfg = field.cfg();
+        let docs = field.docs(options);
+        if let Some(default) = default_arg(field) {
+            let arg_name = if field.borrows.is_empty() {
+                field_name.clone()
+            } else {
+                field.builder_name()
+            };
+            args.push(quote! { #cfg #arg_name: #default });
+            continue;
+        }
+        match make_constructor_arg_type(field, field_info, fake_lifetime, &this, None, false)? {
+            ArgType::Plain(plain_type) => {
+                builder_struct_fields
+                    .push(quote! { #docs #cfg #visibility #field_name: #plain_type });
+                args.push(quote! { #cfg #field_name: self.#field_name });
+                doc_table += &format!(
+                    "| `{}` | Directly pass in the value this field should contain |\n",
+                    field_name
+                );
+            }
+            ArgType::TraitBound(bound_type) => {
+                let builder_name = field.builder_name();
+                let generic_type_name =
+                    format_ident!("{}Builder_", to_class_case(field_name.to_string().as_str()));
+                builder_struct_generics.push(
+                    quote! { #generic_type_name: #bound_type },
+                    quote! { #generic_type_name },
+                    field.cfg_predicate(),
+                );
+                builder_struct_fields
+                    .push(quote! { #docs #cfg #visibility #builder_name: #generic_type_name });
+                args.push(quote! { #cfg #builder_name: self.#builder_name });
+                doc_table += &format!(
+                    "| `{}` | Use a function or closure, as in `{}Builder` |\n",
+                    builder_name, struct_name
+                );
+            }
+        }
+    }
+    let builder_documentation = format!(
+        concat!(
+            "Like [`{0}Builder`]({0}Builder), but fields marked `#[default]` are omitted and ",
+            "take their default values. Call [`build()`](Self::build) to construct the actual ",
+            "struct. The fields of this struct should be used as follows:\n\n",
+            "| Field | Suggested Use |\n| --- | --- |\n{1}",
+        ),
+        struct_name, doc_table
+    );
+    let builder_documentation = if !options.do_no_doc {
+        quote! { #[doc=#builder_documentation] }
+    } else {
+        quote! { #[doc(hidden)] }
+    };
+    let build_fn_documentation = format!(
+        concat!(
+            "Constructs the struct like [`{0}::new()`]({0}::new) using the provided values and ",
+            "the default values."
+        ),
+        struct_name
+    );
+    let regular_builder_name = format_ident!("{}Builder", struct_name);
+    let generic_where = &generic_params.where_clause;
+    let builder_struct_params = builder_struct_generics.params();
+    let builder_impls =
+        builder_struct_generics.make_impls(&builder_struct_name, |producers, consumers| {
+            quote! {
+                impl<#(#producers),*> #builder_struct_name <#(#consumers),*> #generic_where {
+                    #[doc=#build_fn_documentation]
+                    #visibility fn build(self) -> #struct_name <#(#generic_args),*> {
+                        #regular_builder_name { #(#args),* }.build()
+                    }
+                }
+            }
+        });
+    let builder_def = quote! {
+        #builder_documentation
+        #builder_attrs
+        #visibility struct #builder_struct_name <#(#builder_struct_params),*> #generic_where {
+            #(#builder_struct_fields),*
+        }
+        #builder_impls
+    };
+    let default_impl = if builder_struct_fields.is_empty() {
+        quote! {
+            impl #generic_params ::core::default::Default for #struct_name <#(#generic_args),*> #generic_where {
+                fn default() -> Self {
+                    #regular_builder_name { #(#args),* }.build()
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+    Ok((builder_def, default_impl))
+}
+
+/// Returns true if any field is only compiled under some #[cfg()].
+fn has_cfg_fields(field_info: &[StructFieldInfo]) -> bool {
+    field_info.iter().any(|field| !field.cfg_attrs.is_empty())
+}
+
+/// The name of the stage of the step-by-step builder in which the first `built_fields` fields of
+/// the struct have been initialized.
+fn stage_name(struct_name: &Ident, built_fields: usize) -> Ident {
+    format_ident!("{}Stage{}", struct_name, built_fields)
+}
+
+/// Generates `start()` and one `MyStructStageN` struct for every field. Each stage holds the
+/// partially initialized struct and has a single method which initializes the next field and
+/// returns the next stage. The last stage has `finish()` instead. Stages drop the fields they have
+/// initialized if they are dropped before `finish()` is called. Nothing is generated if any field
+/// has a #[cfg()] attribute, since the stages are numbered by field.
+fn make_stage_builders(
+    struct_visibility: &Visibility,
+    struct_name: &Ident,
+    fake_lifetime: &Ident,
+    generic_params: &Generics,
+    generic_args: &[TokenStream2],
+    field_info: &[StructFieldInfo],
+    options: &Options,
+) -> Result<(TokenStream2, TokenStream2), Error> {
+    let this = options.this_lifetime();
+    if has_cfg_fields(field_info) {
+        return Ok((quote! {}, quote! {}));
+    }
+    let visibility = options.item_visibility(&options.vis.builder, struct_visibility);
+    let new_visibility = options.item_visibility(&options.vis.new, struct_visibility);
+    let builder_attrs = options.builder_attrs();
+    let generic_where = &generic_params.where_clause;
+    let struct_type = quote! { #struct_name <#(#generic_args),*> };
+    let documentation = |documentation: String| {
+        if !options.do_no_doc {
+            quote! { #[doc=#documentation] }
+        } else {
+            quote! { #[doc(hidden)] }
+        }
+    };
+    let mut stage_defs = Vec::new();
+    for built_fields in 1..=field_info.len() {
+        let stage = stage_name(struct_name, built_fields);
+        let mut accessors = Vec::new();
+        for (index, field) in field_info[..built_fields].iter().enumerate() {
+            // A field which has been mutably borrowed cannot be accessed at all.
+            let mutably_borrowed = field_info[..built_fields]
+                .iter()
+                .any(|other| other.borrows.iter().any(|b| b.index == index && b.mutable));
+            if mutably_borrowed {
+                continue;
+            }
+            let field_name = &field.name;
+            let field_type = &field.typ;
+            let user_name = format_ident!("with_{}", field_name);
+            let docs = field.docs(options);
+            let accessor_documentation =
+                documentation(format!("Provides an immutable reference to `{}`.", field_name));
+            accessors.push(quote! {
+                #docs
+                #accessor_documentation
+                #visibility fn #user_name <'outer_borrow, ReturnType>(
+                    &'outer_borrow self,
+                    user: impl for<#this> ::core::ops::FnOnce(&'outer_borrow #field_type) -> ReturnType,
+                ) -> ReturnType {
+                    user(unsafe { &(*self.result.as_ptr()).#field_name })
+                }
+            });
+            if !field.self_referencing || field.covariant == Some(true) {
+                let borrower_name = format_ident!("borrow_{}", field_name);
+                // Fields which are mutable references are returned behind an immutable
+                // reference, which clippy mistakes for a mutable reference created from an
+                // immutable one.
+                accessors.push(quote! {
+                    #docs
+                    #accessor_documentation
+                    #[allow(clippy::mut_from_ref)]
+                    #visibility fn #borrower_name<#this>(&#this self) -> &#this #field_type {
+                        unsafe { &(*self.result.as_ptr()).#field_name }
+                    }
+                });
+            }
+        }
+        let (advance_fn, stage_documentation) = if let Some(field) = field_info.get(built_fields) {
+            let field_name = &field.name;
+            let next_stage = stage_name(struct_name, built_fields + 1);
+            let field_type = &field.typ;
+            let field_type =
+                replace_this_with_lifetime(quote! { #field_type }, &this, fake_lifetime.clone());
+            let arg_type =
+                make_constructor_arg_type(field, field_info, fake_lifetime, &this, None, false)?;
+            let (receiver, param, value) = match arg_type {
+                ArgType::Plain(plain_type) => (
+                    quote! { self },
+                    quote! { #field_name: #plain_type },
+                    quote! { #field_name },
+                ),
+                ArgType::TraitBound(bound_type) => {
+                    let builder_name = field.builder_name();
+                    let mut builder_args = Vec::new();
+                    for borrow in &field.borrows {
+                        let borrowed_name = &field_info[borrow.index].name;
+                        builder_args.push(if borrow.mutable {
+                            quote! { unsafe {
+                                ::ouroboros::macro_help::stable_deref_and_change_lifetime_mut(
+                                    &mut (*self.result.as_mut_ptr()).#borrowed_name
+                                )
+                            }}
+                        } else {
+                            quote! { unsafe {
+                                ::ouroboros::macro_help::stable_deref_and_change_lifetime(
+                                    &(*self.result.as_ptr()).#borrowed_name
+                                )
+                            }}
+                        });
+                    }
+                    builder_args
+                        .extend(field.make_peek_references(field_info, quote! { self.result }));
+                    let receiver = if field.borrows.iter().any(|borrow| borrow.mutable) {
+                        quote! { mut self }
+                    } else {
+                        quote! { self }
+                    };
+                    (
+                        receiver,
+                        quote! { #builder_name: impl #bound_type },
+                        quote! { #builder_name(#(#builder_args),*) },
+                    )
+                }
+            };
+            let advance_documentation = documentation(format!(
+                "Initializes `{}` and advances to the next stage.",
+                field_name
+            ));
+            let advance_fn = quote! {
+                #advance_documentation
+                #visibility fn #field_name(#receiver, #param) -> #next_stage <#(#generic_args),*> {
+                    let value = #value;
+                    let this = ::core::mem::ManuallyDrop::new(self);
+                    let mut result = unsafe { ::core::ptr::read(&this.result) };
+                    unsafe {
+                        ((&mut (*result.as_mut_ptr()).#field_name) as *mut #field_type).write(value);
+                    }
+                    #next_stage { result }
+                }
+            };
+            let stage_documentation = format!(
+                concat!(
+                    "A stage of the step-by-step builder started by [`{0}::start()`]({0}::start) ",
+                    "in which the first {1} fields have been initialized. Call ",
+                    "[`{2}()`](Self::{2}) to initialize the next field."
+                ),
+                struct_name, built_fields, field_name
+            );
+            (advance_fn, stage_documentation)
+        } else {
+            let finish_documentation =
+                documentation(format!("Returns the fully initialized `{}`.", struct_name));
+            let advance_fn = quote! {
+                #finish_documentation
+                #visibility fn finish(self) -> #struct_type {
+                    let this = ::core::mem::ManuallyDrop::new(self);
+                    unsafe { ::core::ptr::read(&this.result).assume_init() }
+                }
+            };
+            let stage_documentation = format!(
+                concat!(
+                    "The last stage of the step-by-step builder started by ",
+                    "[`{0}::start()`]({0}::start). Call [`finish()`](Self::finish) to get the ",
+                    "constructed struct."
+                ),
+                struct_name
+            );
+            (advance_fn, stage_documentation)
+        };
+        let stage_documentation = documentation(stage_documentation);
+        // Later fields may borrow from earlier ones, so they are dropped first.
+        let built_names = field_info[..built_fields].iter().rev().map(|field| &field.name);
+        stage_defs.push(quote! {
+            #stage_documentation
+            #builder_attrs
+            #visibility struct #stage #generic_params #generic_where {
+                result: ::core::mem::MaybeUninit<#struct_type>,
+            }
+            impl #generic_params #stage <#(#generic_args),*> #generic_where {
+                #advance_fn
+                #(#accessors)*
+            }
+            impl #generic_params ::core::ops::Drop for #stage <#(#generic_args),*> #generic_where {
+                fn drop(&mut self) {
+                    unsafe {
+                        #(::core::ptr::drop_in_place(&mut (*self.result.as_mut_ptr()).#built_names);)*
+                    }
+                }
+            }
+        });
+    }
+    let first_field = &field_info[0];
+    let first_name = &first_field.name;
+    let first_type = &first_field.typ;
+    let first_type = replace_this_with_lifetime(quote! { #first_type }, &this, fake_lifetime.clone());
+    let first_stage = stage_name(struct_name, 1);
+    let start_documentation = documentation(format!(
+        concat!(
+            "Starts a step-by-step builder with the value of `{0}`. The returned ",
+            "[`{1}`]({1}) has a method for initializing the next field, and so on until every ",
+            "field has been initialized."
+        ),
+        first_name, first_stage
+    ));
+    let start_fn = quote! {
+        #start_documentation
+        #new_visibility fn start(#first_name: #first_type) -> #first_stage <#(#generic_args),*> {
+            let mut result = ::core::mem::MaybeUninit::<Self>::uninit();
+            unsafe {
+                ((&mut (*result.as_mut_ptr()).#first_name) as *mut #first_type).write(#first_name);
+            }
+            #first_stage { result }
+        }
+    };
+    Ok((quote! { #(#stage_defs)* }, start_fn))
+}
+
+/// Returns the error enum used by the try constructors when per_field_errors is enabled. It has one
+/// variant per fallible builder, named after its field, which holds the error returned by that
+/// builder and the head fields of the struct.
+fn make_build_error_enum(
+    struct_visibility: &Visibility,
+    struct_name: &Ident,
+    field_info: &[StructFieldInfo],
+    generic_params: &Generics,
+    generic_args: &[TokenStream2],
+    options: &Options,
+) -> Result<TokenStream2, Error> {
+    if !options.do_per_field_errors {
+        return Ok(quote! {});
+    }
+    let visibility = options.item_visibility(&options.vis.new, struct_visibility);
+    let enum_name = format_ident!("{}BuildError", struct_name);
+    let mut variants = Vec::new();
+    let mut error_params = Vec::new();
+    let mut heads_arms = Vec::new();
+    let mut debug_arms = Vec::new();
+    let mut display_arms = Vec::new();
+    let mut source_arms = Vec::new();
+    let fallible_fields = field_info
+        .iter()
+        .filter(|field| !field.borrows.is_empty() && !field.infallible);
+    for field in fallible_fields {
+        let variant = field.error_variant_name();
+        let error_param = field.error_param_name();
+        let documentation = format!(
+            "The builder for `{}` failed. Contains the error it returned and the head fields.",
+            field.name
+        );
+        variants.push(quote! {
+            #[doc=#documentation]
+            #variant(#error_param, Heads<#(#generic_args),*>)
+        });
+        heads_arms.push(quote! { Self::#variant(_, heads) => heads });
+        let variant_name = variant.to_string();
+        debug_arms.push(quote! {
+            Self::#variant(error, _) => f.debug_tuple(#variant_name).field(error).finish()
+        });
+        let message = format!("failed to build `{}`: {{}}", field.name);
+        display_arms.push(quote! { Self::#variant(error, _) => write!(f, #message, error) });
+        source_arms.push(quote! { Self::#variant(error, _) => ::core::option::Option::Some(error) });
+        error_params.push(error_param);
+    }
+    if error_params.is_empty() {
+        return Err(Error::new(
+            Span::call_site(),
+            concat!(
+                "per_field_errors has no effect when every builder is marked #[infallible], since ",
+                "the try constructors can never fail. Remove per_field_errors."
+            ),
+        ));
+    }
+    let mut all_params = generic_params.clone();
+    for error_param in &error_params {
+        all_params.params.push(syn::parse_quote! { #error_param });
+    }
+    let enum_params = &all_params.params;
+    let enum_args = quote! { #(#generic_args,)* #(#error_params),* };
+    let with_bounds = |bound: TokenStream2| {
+        let mut generics = all_params.clone();
+        let where_clause = generics.make_where_clause();
+        for error_param in &error_params {
+            where_clause
+                .predicates
+                .push(syn::parse_quote! { #error_param: #bound });
+        }
+        generics.where_clause
+    };
+    let generic_where = &generic_params.where_clause;
+    let debug_where = with_bounds(quote! { ::core::fmt::Debug });
+    let display_where = with_bounds(quote! { ::core::fmt::Display });
+    let error_where = with_bounds(quote! { ::std::error::Error + 'static });
+    let documentation = format!(
+        concat!(
+            "The error returned by the try constructors of [`{0}`]({0}). Each builder which can ",
+            "fail has its own variant and error type. Since every variant also holds the ",
+            "[head fields](https://docs.rs/ouroboros/latest/ouroboros/attr.self_referencing.html#definitions), ",
+            "no separate `_or_recover` constructors are generated."
+        ),
+        struct_name
+    );
+    let documentation = if !options.do_no_doc {
+        quote! { #[doc=#documentation] }
+    } else {
+        quote! { #[doc(hidden)] }
+    };
+    Ok(quote! {
+        #documentation
+        #visibility enum #enum_name <#enum_params> #generic_where {
+            #(#variants),*
+        }
+        impl <#enum_params> #enum_name <#enum_args> #generic_where {
+            /// Returns the head fields, discarding the error.
+            #visibility fn into_heads(self) -> Heads<#(#generic_args),*> {
+                match self {
+                    #(#heads_arms),*
+                }
+            }
+        }
+        impl <#enum_params> ::core::fmt::Debug for #enum_name <#enum_args> #debug_where {
+            fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                match self {
+                    #(#debug_arms),*
+                }
+            }
+        }
+        impl <#enum_params> ::core::fmt::Display for #enum_name <#enum_args> #display_where {
+            fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                match self {
+                    #(#display_arms),*
+                }
+            }
+        }
+        impl <#enum_params> ::std::error::Error for #enum_name <#enum_args> #error_where {
+            fn source(&self) -> ::core::option::Option<&(dyn ::std::error::Error + 'static)> {
+                match self {
+                    #(#source_arms),*
+                }
+            }
+        }
+    })
+}
+
+fn make_with_functions(
+    field_info: &[StructFieldInfo],
+    options: &Options,
+) -> Result<Vec<TokenStream2>, Error> {
+    let this = options.this_lifetime();
+    let mut users = Vec::new();
+    for field in field_info {
+        let visibility = &field.vis;
+        let field_name = &field.name;
+        let field_type = &field.typ;
+        let cfg = field.cfg();
+        let docs = field.docs(options);
+        let accessor_attrs = field.accessor_attrs(options);
+        // If the field is not a tail, we need to serve up the same kind of reference that other
+        // fields in the struct may have borrowed to ensure safety.
+        if field.field_type == FieldType::Tail {
+            let user_name = format_ident!("with_{}", &field.name);
+            let documentation = format!(
+                concat!(
+                    "Provides an immutable reference to `{0}`. This method was generated because ",
+                    "`{0}` is a [tail field](https://docs.rs/ouroboros/latest/ouroboros/attr.self_referencing.html#definitions)."
+                ),
+                field.name.to_string()
+            );
+            let documentation = if !options.do_no_doc {
+                quote! {
+                    #[doc=#documentation]
+                }
+            } else {
+                quote! { #[doc(hidden)] }
+            };
+            users.push(quote! {
+                #cfg
+                #docs
+                #documentation
+                #accessor_attrs
+                #visibility fn #user_name <'outer_borrow, ReturnType>(
+                    &'outer_borrow self,
+                    user: impl for<#this> ::core::ops::FnOnce(&'outer_borrow #field_type) -> ReturnType,
+                ) -> ReturnType {
+                    user(&self. #field_name)
+                }
+            });
+            if field.covariant == Some(true) {
+                let borrower_name = format_ident!("borrow_{}", &field.name);
+                users.push(quote! {
+                    #cfg
+                    #docs
+                    #documentation
+                    #accessor_attrs
+                    #visibility fn #borrower_name<#this>(
+                        &#this self,
+                    ) -> &#this #field_type {
+                        &self.#field_name
+                    }
+                });
+            } else if field.covariant.is_none() {
+                field.covariance_error(&this);
             }
             // If it is not borrowed at all it's safe to allow mutably borrowing it.
             let user_name = format_ident!("with_{}_mut", &field.name);
@@ -1166,7 +3316,7 @@ fn make_with_functions(
                 ),
                 field.name.to_string()
             );
-            let documentation = if !do_no_doc {
+            let documentation = if !options.do_no_doc {
                 quote! {
                     #[doc=#documentation]
                 }
@@ -1174,10 +3324,13 @@ fn make_with_functions(
                 quote! { #[doc(hidden)] }
             };
             users.push(quote! {
+                #cfg
+                #docs
                 #documentation
+                #accessor_attrs
                 #visibility fn #user_name <'outer_borrow, ReturnType>(
                     &'outer_borrow mut self,
-                    user: impl for<'this> ::core::ops::FnOnce(&'outer_borrow mut #field_type) -> ReturnType,
+                    user: impl for<#this> ::core::ops::FnOnce(&'outer_borrow mut #field_type) -> ReturnType,
                 ) -> ReturnType {
                     user(&mut self. #field_name)
                 }
@@ -1191,7 +3344,7 @@ fn make_with_functions(
                 ),
                 field.name.to_string()
             );
-            let documentation = if !do_no_doc {
+            let documentation = if !options.do_no_doc {
                 quote! {
                     #[doc=#documentation]
                 }
@@ -1199,10 +3352,13 @@ fn make_with_functions(
                 quote! { #[doc(hidden)] }
             };
             users.push(quote! {
+                #cfg
+                #docs
                 #documentation
+                #accessor_attrs
                 #visibility fn #user_name <'outer_borrow, ReturnType>(
                     &'outer_borrow self,
-                    user: impl for<'this> ::core::ops::FnOnce(&'outer_borrow #field_type) -> ReturnType,
+                    user: impl for<#this> ::core::ops::FnOnce(&'outer_borrow #field_type) -> ReturnType,
                 ) -> ReturnType {
                     user(&self.#field_name)
                 }
@@ -1212,15 +3368,18 @@ fn make_with_functions(
                     // Skip the other functions, they will cause compiler errors.
                     continue;
                 } else if field.covariant.is_none() {
-                    field.covariance_error();
+                    field.covariance_error(&this);
                 }
             }
             let borrower_name = format_ident!("borrow_{}", &field.name);
             users.push(quote! {
+                #cfg
+                #docs
                 #documentation
-                #visibility fn #borrower_name<'this>(
-                    &'this self,
-                ) -> &'this #field_type {
+                #accessor_attrs
+                #visibility fn #borrower_name<#this>(
+                    &#this self,
+                ) -> &#this #field_type {
                     &self.#field_name
                 }
             });
@@ -1239,14 +3398,11 @@ fn make_with_all_function(
     field_info: &[StructFieldInfo],
     generic_params: &Generics,
     generic_args: &[TokenStream2],
-    do_no_doc: bool,
-    do_pub_extras: bool,
+    options: &Options,
 ) -> Result<(TokenStream2, TokenStream2), Error> {
-    let visibility = if do_pub_extras {
-        struct_visibility.clone()
-    } else {
-        syn::parse_quote! { pub(super) }
-    };
+    let this = options.this_lifetime();
+    let visibility = options.item_visibility(&options.vis.with, struct_visibility);
+    let mut_visibility = options.item_visibility(&options.vis.with_mut, struct_visibility);
     let mut fields = Vec::new();
     let mut field_assignments = Vec::new();
     let mut mut_fields = Vec::new();
@@ -1255,21 +3411,25 @@ fn make_with_all_function(
     for field in field_info.iter().rev() {
         let field_name = &field.name;
         let field_type = &field.typ;
+        let cfg = field.cfg();
+        let docs = field.docs(options);
         if field.field_type == FieldType::Tail {
-            fields.push(quote! { #visibility #field_name: &'outer_borrow #field_type });
-            field_assignments.push(quote! { #field_name: &self.#field_name });
-            mut_fields.push(quote! { #visibility #field_name: &'outer_borrow mut #field_type });
-            mut_field_assignments.push(quote! { #field_name: &mut self.#field_name });
+            fields.push(quote! { #docs #cfg #visibility #field_name: &'outer_borrow #field_type });
+            field_assignments.push(quote! { #cfg #field_name: &self.#field_name });
+            mut_fields.push(quote! {
+                #docs #cfg #mut_visibility #field_name: &'outer_borrow mut #field_type
+            });
+            mut_field_assignments.push(quote! { #cfg #field_name: &mut self.#field_name });
         } else if field.field_type == FieldType::Borrowed {
-            let ass = quote! { #field_name: unsafe {
+            let ass = quote! { #cfg #field_name: unsafe {
                 ::ouroboros::macro_help::stable_deref_and_change_lifetime(
                     &self.#field_name
                 )
             } };
             let deref_type = quote! { <#field_type as ::std::ops::Deref>::Target };
-            fields.push(quote! { #visibility #field_name: &'this #deref_type });
+            fields.push(quote! { #docs #cfg #visibility #field_name: &#this #deref_type });
             field_assignments.push(ass.clone());
-            mut_fields.push(quote! { #visibility #field_name: &'this #deref_type });
+            mut_fields.push(quote! { #docs #cfg #mut_visibility #field_name: &#this #deref_type });
             mut_field_assignments.push(ass);
         } else if field.field_type == FieldType::BorrowedMut {
             // Add nothing because we cannot borrow something that has already been mutably
@@ -1278,7 +3438,7 @@ fn make_with_all_function(
     }
 
     let new_generic_params = if generic_params.params.is_empty() {
-        quote! { <'outer_borrow, 'this> }
+        quote! { <'outer_borrow, #this> }
     } else {
         for (ty, ident) in make_template_consumers(generic_params) {
             fields.push(quote! { #ident: ::core::marker::PhantomData<#ty> });
@@ -1289,7 +3449,7 @@ fn make_with_all_function(
         let mut new_generic_params = generic_params.clone();
         new_generic_params
             .params
-            .insert(0, syn::parse_quote! { 'this });
+            .insert(0, syn::parse_quote! { #this });
         new_generic_params
             .params
             .insert(0, syn::parse_quote! { 'outer_borrow });
@@ -1297,7 +3457,7 @@ fn make_with_all_function(
     };
     let new_generic_args = {
         let mut args = Vec::from(generic_args);
-        args.insert(0, quote! { 'this });
+        args.insert(0, quote! { #this });
         args.insert(0, quote! { 'outer_borrow });
         args
     };
@@ -1322,19 +3482,21 @@ fn make_with_all_function(
     let lifetime = Lifetime::new(&ltname, Span::call_site());
     let generic_where = if let Some(clause) = &generic_params.where_clause {
         let mut clause = clause.clone();
-        let extra: WhereClause = syn::parse_quote! { where #lifetime: 'this };
         clause
             .predicates
-            .push(extra.predicates.first().unwrap().clone());
+            .push(syn::parse_quote! { #lifetime: #this });
         clause
     } else {
-        syn::parse_quote! { where #lifetime: 'this }
+        syn::parse_quote! { where #lifetime: #this }
     };
+    let fields_attrs = options.fields_attrs();
     let struct_defs = quote! {
         #[doc=#struct_documentation]
+        #fields_attrs
         #visibility struct BorrowedFields #new_generic_params #generic_where { #(#fields),* }
         #[doc=#mut_struct_documentation]
-        #visibility struct BorrowedMutFields #new_generic_params #generic_where { #(#mut_fields),* }
+        #fields_attrs
+        #mut_visibility struct BorrowedMutFields #new_generic_params #generic_where { #(#mut_fields),* }
     };
     let borrowed_fields_type = quote! { BorrowedFields<#(#new_generic_args),*> };
     let borrowed_mut_fields_type = quote! { BorrowedMutFields<#(#new_generic_args),*> };
@@ -1346,140 +3508,836 @@ fn make_with_all_function(
         "This method provides mutable references to all ",
         "[tail fields](https://docs.rs/ouroboros/latest/ouroboros/attr.self_referencing.html#definitions).",
     );
-    let documentation = if !do_no_doc {
+    let documentation = if !options.do_no_doc {
         quote! {
             #[doc=#documentation]
         }
     } else {
         quote! { #[doc(hidden)] }
     };
-    let mut_documentation = if !do_no_doc {
+    let mut_documentation = if !options.do_no_doc {
         quote! {
             #[doc=#mut_documentation]
         }
     } else {
         quote! { #[doc(hidden)] }
     };
+    let accessor_attrs = options.accessor_attrs();
     let fn_defs = quote! {
         #documentation
+        #accessor_attrs
         #visibility fn with <'outer_borrow, ReturnType>(
             &'outer_borrow self,
-            user: impl for<'this> ::core::ops::FnOnce(#borrowed_fields_type) -> ReturnType
+            user: impl for<#this> ::core::ops::FnOnce(#borrowed_fields_type) -> ReturnType
         ) -> ReturnType {
             user(BorrowedFields {
                 #(#field_assignments),*
             })
         }
         #mut_documentation
-        #visibility fn with_mut <'outer_borrow, ReturnType>(
+        #accessor_attrs
+        #mut_visibility fn with_mut <'outer_borrow, ReturnType>(
             &'outer_borrow mut self,
-            user: impl for<'this> ::core::ops::FnOnce(#borrowed_mut_fields_type) -> ReturnType
+            user: impl for<#this> ::core::ops::FnOnce(#borrowed_mut_fields_type) -> ReturnType
         ) -> ReturnType {
             user(BorrowedMutFields {
                 #(#mut_field_assignments),*
             })
         }
-    };
-    Ok((struct_defs, fn_defs))
+    };
+    Ok((struct_defs, fn_defs))
+}
+
+/// Returns the Heads struct and a function to convert the original struct into a Heads instance.
+fn make_into_heads(
+    struct_visibility: &Visibility,
+    struct_name: &Ident,
+    field_info: &[StructFieldInfo],
+    generic_params: &Generics,
+    generic_args: &[TokenStream2],
+    options: &Options,
+) -> (TokenStream2, TokenStream2) {
+    let this = options.this_lifetime();
+    let visibility = options.item_visibility(&options.vis.into_heads, struct_visibility);
+    let heads_visibility = options.item_visibility(&options.vis.heads, struct_visibility);
+    let mut code = Vec::new();
+    let mut field_initializers = Vec::new();
+    let mut head_fields = Vec::new();
+    if let Some(on_drop) = &options.on_drop {
+        // The struct implements Drop, so fields cannot be moved out of it directly. Instead we
+        // run the hook ourselves and then take the struct apart without running its destructor.
+        // The result of with_mut is discarded explicitly since accessor_attrs may mark it
+        // #[must_use], and into_heads allows it to be #[deprecated].
+        code.push(quote! {
+            let mut this = ::core::mem::ManuallyDrop::new(self);
+            let _ = this.with_mut(|fields| #on_drop(fields));
+        });
+    }
+    // Drop everything in the reverse order of what it was declared in. Fields that come later
+    // are only dependent on fields that came before them.
+    for field in field_info.iter().rev() {
+        let field_name = &field.name;
+        let cfg = field.cfg();
+        let docs = field.docs(options);
+        if !field.self_referencing {
+            if options.on_drop.is_some() {
+                code.push(quote! {
+                    #cfg let #field_name = unsafe { ::core::ptr::read(&this.#field_name) };
+                });
+            } else {
+                code.push(quote! { #cfg let #field_name = self.#field_name; });
+            }
+            field_initializers.push(quote! { #cfg #field_name });
+            let field_type = &field.typ;
+            head_fields.push(quote! { #docs #cfg #heads_visibility #field_name: #field_type });
+        } else if options.on_drop.is_some() {
+            code.push(quote! {
+                #cfg unsafe { ::core::ptr::drop_in_place(&mut this.#field_name) };
+            });
+        } else {
+            // Heads are fields that do not borrow anything.
+            code.push(quote! { #cfg ::core::mem::drop(self.#field_name); });
+        }
+    }
+    for (ty, ident) in make_template_consumers(generic_params) {
+        head_fields.push(quote! { #ident: ::core::marker::PhantomData<#ty> });
+        field_initializers.push(quote! { #ident: ::core::marker::PhantomData });
+    }
+    let documentation = format!(
+        concat!(
+            "A struct which contains only the ",
+            "[head fields](https://docs.rs/ouroboros/latest/ouroboros/attr.self_referencing.html#definitions) of [`{0}`]({0})."
+        ),
+        struct_name.to_string()
+    );
+    let generic_where = &generic_params.where_clause;
+    let heads_attrs = options.heads_attrs();
+    let heads_struct_def = quote! {
+        #[doc=#documentation]
+        #heads_attrs
+        #heads_visibility struct Heads #generic_params #generic_where {
+            #(#head_fields),*
+        }
+    };
+    let documentation = concat!(
+        "This function drops all internally referencing fields and returns only the ",
+        "[head fields](https://docs.rs/ouroboros/latest/ouroboros/attr.self_referencing.html#definitions) of this struct."
+    ).to_owned();
+
+    let documentation = if !options.do_no_doc {
+        quote! {
+            #[doc=#documentation]
+        }
+    } else {
+        quote! { #[doc(hidden)] }
+    };
+
+    let and_documentation = concat!(
+        "Like `into_heads`, but first calls `user` with mutable references to all ",
+        "[tail fields](https://docs.rs/ouroboros/latest/ouroboros/attr.self_referencing.html#definitions), ",
+        "returning its result alongside the heads."
+    );
+    let and_documentation = if !options.do_no_doc {
+        quote! {
+            #[doc=#and_documentation]
+        }
+    } else {
+        quote! { #[doc(hidden)] }
+    };
+
+    let into_heads_fn = quote! {
+        #documentation
+        #[allow(clippy::drop_ref)]
+        #[allow(clippy::drop_copy)]
+        #[allow(clippy::drop_non_drop)]
+        #[allow(deprecated)]
+        #visibility fn into_heads(self) -> Heads<#(#generic_args),*> {
+            #(#code)*
+            Heads {
+                #(#field_initializers),*
+            }
+        }
+        #and_documentation
+        #[allow(deprecated)]
+        #visibility fn into_heads_and<ReturnType>(
+            mut self,
+            user: impl for<'outer_borrow, #this> ::core::ops::FnOnce(
+                BorrowedMutFields<'outer_borrow, #this, #(#generic_args),*>
+            ) -> ReturnType,
+        ) -> (Heads<#(#generic_args),*>, ReturnType) {
+            let result = self.with_mut(user);
+            (self.into_heads(), result)
+        }
+    };
+    (heads_struct_def, into_heads_fn)
+}
+
+/// Returns the view structs and share_* functions for every field marked with #[share]. A view
+/// holds a clone of the field along with clones of the Arc or Rc heads it borrows from. Since
+/// cloning those heads does not move the data they point to, the cloned field stays valid for as
+/// long as the view exists.
+fn make_share_functions(
+    struct_name: &Ident,
+    fake_lifetime: &Ident,
+    field_info: &[StructFieldInfo],
+    generic_params: &Generics,
+    generic_args: &[TokenStream2],
+    options: &Options,
+) -> Result<(Vec<TokenStream2>, Vec<TokenStream2>), Error> {
+    let this = options.this_lifetime();
+    let mut view_defs = Vec::new();
+    let mut share_fns = Vec::new();
+    for field in field_info {
+        if !field.share {
+            continue;
+        }
+        if !field.self_referencing {
+            return Err(Error::new_spanned(
+                &field.typ,
+                "#[share] can only be used on fields which borrow from other fields.",
+            ));
+        }
+        let mut heads = Vec::new();
+        for borrow in &field.borrows {
+            let head = &field_info[borrow.index];
+            let is_shared_pointer = matches!(
+                apparent_std_container_type(&head.typ),
+                Some(("Arc", _)) | Some(("Rc", _))
+            );
+            if borrow.mutable || head.self_referencing || !is_shared_pointer {
+                return Err(Error::new_spanned(
+                    &field.typ,
+                    format!(
+                        concat!(
+                            "#[share] can only be used on fields which immutably borrow head ",
+                            "fields of type Arc or Rc, but `{}` is not one."
+                        ),
+                        head.name
+                    ),
+                ));
+            }
+            heads.push(head);
+        }
+        let field_name = &field.name;
+        let field_type = &field.typ;
+        let static_field_type =
+            replace_this_with_lifetime(quote! { #field_type }, &this, fake_lifetime.clone());
+        let view_name = field.view_name(struct_name);
+        let visibility = &field.vis;
+        let cfg = field.cfg();
+        // The field is stored with 'this replaced by a fake lifetime, so it is cloned through a
+        // function which works for any 'this. Otherwise a Clone implementation which only exists
+        // for the fake lifetime could be called and rely on the field being borrowed for it.
+        let mut clone_fn_generics = generic_params.clone();
+        clone_fn_generics.params.insert(
+            0,
+            GenericParam::Lifetime(syn::LifetimeDef::new(this.clone())),
+        );
+        clone_fn_generics
+            .make_where_clause()
+            .predicates
+            .push(syn::parse_quote! { #field_type: ::core::clone::Clone });
+        let clone_fn_where = &clone_fn_generics.where_clause;
+        let mut view_fields = vec![quote! { #field_name: #static_field_type }];
+        let mut initializers = vec![quote! {
+            #field_name: {
+                fn clone_field #clone_fn_generics (field: &#field_type) -> #field_type
+                #clone_fn_where
+                {
+                    ::core::clone::Clone::clone(field)
+                }
+                clone_field(&self.#field_name)
+            }
+        }];
+        let mut view_users = Vec::new();
+        for head in &heads {
+            let head_name = &head.name;
+            let head_type = &head.typ;
+            view_fields.push(quote! { #head_name: #head_type });
+            initializers.push(quote! { #head_name: ::core::clone::Clone::clone(&self.#head_name) });
+            let borrower_name = format_ident!("borrow_{}", head_name);
+            let documentation = format!("Provides immutable access to `{}`.", head_name);
+            view_users.push(quote! {
+                #[doc=#documentation]
+                #visibility fn #borrower_name<#this>(&#this self) -> &#this #head_type {
+                    &self.#head_name
+                }
+            });
+        }
+        for (ty, ident) in make_template_consumers(generic_params) {
+            view_fields.push(quote! { #ident: ::core::marker::PhantomData<#ty> });
+            initializers.push(quote! { #ident: ::core::marker::PhantomData });
+        }
+        let documentation = format!(
+            "Provides an immutable reference to `{0}`, which borrows from data shared with [`{1}`]({1}).",
+            field_name, struct_name
+        );
+        let user_name = format_ident!("with_{}", field_name);
+        view_users.push(quote! {
+            #[doc=#documentation]
+            #visibility fn #user_name <'outer_borrow, ReturnType>(
+                &'outer_borrow self,
+                user: impl for<#this> ::core::ops::FnOnce(&'outer_borrow #field_type) -> ReturnType,
+            ) -> ReturnType {
+                user(&self.#field_name)
+            }
+        });
+        if field.covariant == Some(true) {
+            let borrower_name = format_ident!("borrow_{}", field_name);
+            view_users.push(quote! {
+                #[doc=#documentation]
+                #visibility fn #borrower_name<#this>(&#this self) -> &#this #field_type {
+                    &self.#field_name
+                }
+            });
+        }
+        let mut clone_generics = generic_params.clone();
+        clone_generics
+            .make_where_clause()
+            .predicates
+            .push(syn::parse_quote! { for<#this> #field_type: ::core::clone::Clone });
+        let clone_where = &clone_generics.where_clause;
+        let generic_where = &generic_params.where_clause;
+        let documentation = format!(
+            concat!(
+                "A clone of `{0}` which keeps the data it borrows from alive by sharing ",
+                "ownership of it with [`{1}`]({1}). Created by `{1}::share_{0}`."
+            ),
+            field_name, struct_name
+        );
+        view_defs.push(quote! {
+            #cfg
+            #[doc=#documentation]
+            #visibility struct #view_name #generic_params #generic_where {
+                // The field is declared first so that it is dropped before the heads it borrows.
+                #(#view_fields),*
+            }
+            #cfg
+            impl #generic_params #view_name <#(#generic_args),*> #generic_where {
+                #(#view_users)*
+            }
+            #cfg
+            impl #generic_params ::core::clone::Clone for #view_name <#(#generic_args),*> #clone_where {
+                fn clone(&self) -> Self {
+                    Self {
+                        #(#initializers),*
+                    }
+                }
+            }
+        });
+        let share_name = format_ident!("share_{}", field_name);
+        let documentation = format!(
+            concat!(
+                "Returns a clone of `{0}` bundled with clones of the heads it borrows from. This ",
+                "method was generated because `{0}` is marked with ",
+                "[`#[share]`](https://docs.rs/ouroboros/latest/ouroboros/attr.self_referencing.html#sharing-fields)."
+            ),
+            field_name
+        );
+        let documentation = if !options.do_no_doc {
+            quote! { #[doc=#documentation] }
+        } else {
+            quote! { #[doc(hidden)] }
+        };
+        share_fns.push(quote! {
+            #cfg
+            #documentation
+            #visibility fn #share_name(&self) -> #view_name <#(#generic_args),*>
+            where
+                for<#this> #field_type: ::core::clone::Clone,
+            {
+                #view_name {
+                    #(#initializers),*
+                }
+            }
+        });
+    }
+    Ok((view_defs, share_fns))
+}
+
+/// Returns a Deref implementation targeting the field marked with #[deref], if there is one.
+fn make_deref_impl(
+    struct_name: &Ident,
+    field_info: &[StructFieldInfo],
+    generic_params: &Generics,
+    generic_args: &[TokenStream2],
+    options: &Options,
+) -> Result<TokenStream2, Error> {
+    let this = options.this_lifetime();
+    let field = if let Some(field) = field_info.iter().find(|field| field.deref) {
+        field
+    } else {
+        return Ok(quote! {});
+    };
+    let field_name = &field.name;
+    let field_type = &field.typ;
+    if field.field_type == FieldType::BorrowedMut {
+        return Err(Error::new_spanned(
+            field_type,
+            concat!(
+                "#[deref] cannot be used on a field which is mutably borrowed by another field, ",
+                "since no other references to it may exist."
+            ),
+        ));
+    }
+    let (target, deref_code) = if !uses_this_lifetime(quote! { #field_type }, &this) {
+        (quote! { #field_type }, quote! { &self.#field_name })
+    } else {
+        // Deref::Target cannot name the lifetime of the borrow of self, so the only case we can
+        // support is when 'this is the lifetime of the outermost reference. That reference can
+        // then be shortened to the borrow of self by dereferencing it.
+        match field_type {
+            Type::Reference(reference)
+                if reference.lifetime.as_ref() == Some(&this)
+                    && !uses_this_lifetime(reference.elem.to_token_stream(), &this) =>
+            {
+                let elem = &reference.elem;
+                (quote! { #elem }, quote! { &*self.#field_name })
+            }
+            _ => {
+                return Err(Error::new_spanned(
+                    field_type,
+                    format!(
+                        concat!(
+                            "#[deref] can only be used on fields whose type does not use {0} or ",
+                            "whose type is a reference like &{0} T. Deref::Target cannot refer to ",
+                            "the lifetime of the borrow of self, so other types would have to ",
+                            "leak {0}. Consider marking the field #[covariant] and using the ",
+                            "generated borrow_* method instead."
+                        ),
+                        this
+                    ),
+                ))
+            }
+        }
+    };
+    let generic_where = &generic_params.where_clause;
+    let cfg = field.cfg();
+    Ok(quote! {
+        #cfg
+        impl #generic_params ::core::ops::Deref for #struct_name <#(#generic_args),*> #generic_where {
+            type Target = #target;
+            fn deref(&self) -> &Self::Target {
+                #deref_code
+            }
+        }
+    })
+}
+
+/// The traits which #[delegate()] can forward, along with every path they can be written as.
+const DELEGATED_TRAITS: &[(&str, &[&str])] = &[
+    (
+        "Iterator",
+        &["Iterator", "core::iter::Iterator", "std::iter::Iterator"],
+    ),
+    (
+        "DoubleEndedIterator",
+        &[
+            "DoubleEndedIterator",
+            "core::iter::DoubleEndedIterator",
+            "std::iter::DoubleEndedIterator",
+        ],
+    ),
+    (
+        "ExactSizeIterator",
+        &[
+            "ExactSizeIterator",
+            "core::iter::ExactSizeIterator",
+            "std::iter::ExactSizeIterator",
+        ],
+    ),
+    ("Read", &["std::io::Read"]),
+    ("Write", &["std::io::Write"]),
+    (
+        "Stream",
+        &[
+            "futures::Stream",
+            "futures::stream::Stream",
+            "futures_core::Stream",
+            "futures_core::stream::Stream",
+        ],
+    ),
+];
+
+/// Splits a path from #[delegate()] into the name of the trait it refers to, the path without
+/// generic arguments, and the type given for `Item` if it was written like `Iterator<Item = T>`.
+fn parse_delegated_trait(
+    trait_path: &syn::Path,
+) -> Result<(&'static str, syn::Path, Option<Type>), Error> {
+    let mut bare_path = trait_path.clone();
+    let mut item = None;
+    if let Some(last) = bare_path.segments.last_mut() {
+        match std::mem::replace(&mut last.arguments, PathArguments::None) {
+            PathArguments::None => (),
+            PathArguments::AngleBracketed(args) if args.args.len() == 1 => match &args.args[0] {
+                GenericArgument::Binding(binding) if binding.ident == "Item" => {
+                    item = Some(binding.ty.clone())
+                }
+                _ => return Err(Error::new_spanned(args, "Expected `<Item = ...>`.")),
+            },
+            args => return Err(Error::new_spanned(args, "Expected `<Item = ...>`.")),
+        }
+    }
+    let written = bare_path
+        .segments
+        .iter()
+        .map(|segment| segment.ident.to_string())
+        .collect::<Vec<_>>()
+        .join("::");
+    let plain = bare_path
+        .segments
+        .iter()
+        .all(|segment| segment.arguments.is_empty());
+    for (name, paths) in DELEGATED_TRAITS {
+        if plain && paths.contains(&&written[..]) {
+            if item.is_some() && *name != "Iterator" && *name != "Stream" {
+                return Err(Error::new_spanned(
+                    trait_path,
+                    format!("{} does not have an Item type.", name),
+                ));
+            }
+            return Ok((name, bare_path, item));
+        }
+    }
+    Err(Error::new_spanned(
+        trait_path,
+        concat!(
+            "Unsupported trait for #[delegate()], expected one of Iterator, ",
+            "DoubleEndedIterator, ExactSizeIterator, std::io::Read, std::io::Write, or ",
+            "futures::Stream."
+        ),
+    ))
+}
+
+/// Returns the Item type of a delegated Iterator or Stream. The items are moved out of the struct,
+/// so they can't borrow from 'this. When the type of the field mentions 'this, the macro can't tell
+/// whether the items do too, so the Item type has to be written out.
+fn make_delegated_item_type(
+    field_type: &Type,
+    trait_path: &syn::Path,
+    written_path: &syn::Path,
+    item: Option<Type>,
+    this: &Lifetime,
+) -> Result<TokenStream2, Error> {
+    if let Some(item) = item {
+        if uses_this_lifetime(item.to_token_stream(), this) {
+            return Err(Error::new_spanned(
+                item,
+                format!(
+                    concat!(
+                        "The items of a delegated trait cannot borrow from '{0}, since they could ",
+                        "outlive the struct. Try producing owned values instead."
+                    ),
+                    this.ident
+                ),
+            ));
+        }
+        Ok(quote! { #item })
+    } else if uses_this_lifetime(field_type.to_token_stream(), this) {
+        Err(Error::new_spanned(
+            written_path,
+            format!(
+                concat!(
+                    "The type of this field mentions '{0}, so the Item type must be written out to ",
+                    "check that it does not borrow from '{0}, e.g. `#[delegate({1}<Item = char>)]`."
+                ),
+                this.ident,
+                trait_path.to_token_stream().to_string().replace(' ', ""),
+            ),
+        ))
+    } else {
+        Ok(quote! { <#field_type as #trait_path>::Item })
+    }
+}
+
+/// Returns implementations of all the traits listed in #[delegate()] annotations. Every method
+/// forwards to the field through with_FIELD or with_FIELD_mut.
+fn make_delegate_impls(
+    struct_name: &Ident,
+    field_info: &[StructFieldInfo],
+    generic_params: &Generics,
+    generic_args: &[TokenStream2],
+    options: &Options,
+) -> Result<Vec<TokenStream2>, Error> {
+    let this = options.this_lifetime();
+    let generic_where = &generic_params.where_clause;
+    let mut impls = Vec::new();
+    for field in field_info {
+        if field.delegates.is_empty() {
+            continue;
+        }
+        if field.field_type != FieldType::Tail {
+            return Err(Error::new_spanned(
+                &field.typ,
+                concat!(
+                    "#[delegate()] can only be used on tail fields, since forwarding requires ",
+                    "mutable access to the field."
+                ),
+            ));
+        }
+        let field_type = &field.typ;
+        let with_name = format_ident!("with_{}", field.name);
+        let with_mut_name = format_ident!("with_{}_mut", field.name);
+        let cfg = field.cfg();
+        for written_path in &field.delegates {
+            let (trait_name, trait_path, item) = parse_delegated_trait(written_path)?;
+            let body = match trait_name {
+                "Iterator" => {
+                    let item =
+                        make_delegated_item_type(field_type, &trait_path, written_path, item, &this)?;
+                    quote! {
+                        type Item = #item;
+                        fn next(&mut self) -> ::core::option::Option<Self::Item> {
+                            self.#with_mut_name(|field| #trait_path::next(field))
+                        }
+                        fn size_hint(&self) -> (usize, ::core::option::Option<usize>) {
+                            self.#with_name(|field| #trait_path::size_hint(field))
+                        }
+                    }
+                }
+                "DoubleEndedIterator" => quote! {
+                    fn next_back(&mut self) -> ::core::option::Option<Self::Item> {
+                        self.#with_mut_name(|field| #trait_path::next_back(field))
+                    }
+                },
+                "ExactSizeIterator" => quote! {
+                    fn len(&self) -> usize {
+                        self.#with_name(|field| #trait_path::len(field))
+                    }
+                },
+                "Read" => quote! {
+                    fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize> {
+                        self.#with_mut_name(|field| #trait_path::read(field, buf))
+                    }
+                },
+                "Write" => quote! {
+                    fn write(&mut self, buf: &[u8]) -> ::std::io::Result<usize> {
+                        self.#with_mut_name(|field| #trait_path::write(field, buf))
+                    }
+                    fn flush(&mut self) -> ::std::io::Result<()> {
+                        self.#with_mut_name(|field| #trait_path::flush(field))
+                    }
+                },
+                _ => {
+                    let item =
+                        make_delegated_item_type(field_type, &trait_path, written_path, item, &this)?;
+                    quote! {
+                        type Item = #item;
+                        fn poll_next(
+                            self: ::core::pin::Pin<&mut Self>,
+                            cx: &mut ::core::task::Context<'_>,
+                        ) -> ::core::task::Poll<::core::option::Option<Self::Item>> {
+                            ::core::pin::Pin::get_mut(self).#with_mut_name(|field| {
+                                #trait_path::poll_next(::core::pin::Pin::new(field), cx)
+                            })
+                        }
+                        fn size_hint(&self) -> (usize, ::core::option::Option<usize>) {
+                            self.#with_name(|field| #trait_path::size_hint(field))
+                        }
+                    }
+                }
+            };
+            // The accessors may have been deprecated through accessor_attrs.
+            impls.push(quote! {
+                #cfg
+                #[allow(deprecated)]
+                impl #generic_params #trait_path for #struct_name <#(#generic_args),*> #generic_where {
+                    #body
+                }
+            });
+        }
+    }
+    Ok(impls)
 }
 
-/// Returns the Heads struct and a function to convert the original struct into a Heads instance.
-fn make_into_heads(
-    struct_visibility: &Visibility,
+/// Returns implementations of the traits listed in derive(), which compare and hash the struct by
+/// looking at the fields marked #[compare], or at every field if none are marked. The fields are
+/// accessed through their borrow_* methods, so fields which don't have one can't be compared.
+fn make_compare_impls(
     struct_name: &Ident,
     field_info: &[StructFieldInfo],
     generic_params: &Generics,
     generic_args: &[TokenStream2],
-    do_no_doc: bool,
-    do_pub_extras: bool,
-) -> (TokenStream2, TokenStream2) {
-    let visibility = if do_pub_extras {
-        struct_visibility.clone()
-    } else {
-        syn::parse_quote! { pub(super) }
-    };
-    let mut code = Vec::new();
-    let mut field_initializers = Vec::new();
-    let mut head_fields = Vec::new();
-    // Drop everything in the reverse order of what it was declared in. Fields that come later
-    // are only dependent on fields that came before them.
-    for field in field_info.iter().rev() {
-        let field_name = &field.name;
-        if !field.self_referencing {
-            code.push(quote! { let #field_name = self.#field_name; });
-            field_initializers.push(quote! { #field_name });
-            let field_type = &field.typ;
-            head_fields.push(quote! { #visibility #field_name: #field_type });
-        } else {
-            // Heads are fields that do not borrow anything.
-            code.push(quote! { ::core::mem::drop(self.#field_name); });
-        }
+    options: &Options,
+) -> Result<Vec<TokenStream2>, Error> {
+    if options.derives.is_empty() {
+        return Ok(Vec::new());
     }
-    for (ty, ident) in make_template_consumers(generic_params) {
-        head_fields.push(quote! { #ident: ::core::marker::PhantomData<#ty> });
-        field_initializers.push(quote! { #ident: ::core::marker::PhantomData });
+    let only_marked = field_info.iter().any(|field| field.compare);
+    let mut borrowers = Vec::new();
+    for field in field_info {
+        if only_marked && !field.compare {
+            continue;
+        }
+        // This mirrors the conditions under which make_with_functions generates borrow_*.
+        let has_borrower = match field.field_type {
+            FieldType::Tail => field.covariant == Some(true),
+            FieldType::Borrowed => !field.self_referencing || field.covariant == Some(true),
+            FieldType::BorrowedMut => false,
+        };
+        let problem = if has_borrower {
+            let borrower_name = format_ident!("borrow_{}", field.name);
+            borrowers.push((field.cfg(), borrower_name));
+            continue;
+        } else if field.field_type == FieldType::BorrowedMut {
+            "it is mutably borrowed by another field, so no other references to it may exist"
+        } else if field.covariant.is_none() {
+            // make_with_functions already reports that covariance has to be specified.
+            continue;
+        } else {
+            "it is not covariant, so no borrow_* method is generated for it"
+        };
+        let help = if only_marked {
+            ""
+        } else {
+            " Mark the fields which should be compared with #[compare]."
+        };
+        return Err(Error::new_spanned(
+            &field.typ,
+            format!("`{}` cannot be compared because {}.{}", field.name, problem, help),
+        ));
     }
-    let documentation = format!(
-        concat!(
-            "A struct which contains only the ",
-            "[head fields](https://docs.rs/ouroboros/latest/ouroboros/attr.self_referencing.html#definitions) of [`{0}`]({0})."
-        ),
-        struct_name.to_string()
-    );
+    let cfgs: Vec<_> = borrowers.iter().map(|(cfg, _)| cfg).collect();
+    let borrowers: Vec<_> = borrowers.iter().map(|(_, borrower)| borrower).collect();
     let generic_where = &generic_params.where_clause;
-    let heads_struct_def = quote! {
-        #[doc=#documentation]
-        #visibility struct Heads #generic_params #generic_where {
-            #(#head_fields),*
+    let mut impls = Vec::new();
+    for trait_path in &options.derives {
+        let trait_name = match trait_path.segments.last() {
+            Some(segment) => segment.ident.to_string(),
+            None => continue,
+        };
+        let (bound, body) = match &trait_name[..] {
+            "PartialEq" => (
+                quote! { ::core::cmp::PartialEq },
+                quote! {
+                    fn eq(&self, other: &Self) -> bool {
+                        #(
+                            #cfgs
+                            if ::core::cmp::PartialEq::ne(self.#borrowers(), other.#borrowers()) {
+                                return false;
+                            }
+                        )*
+                        true
+                    }
+                },
+            ),
+            "Eq" => (quote! { ::core::cmp::Eq }, quote! {}),
+            "Hash" => (
+                quote! { ::core::hash::Hash },
+                quote! {
+                    fn hash<H: ::core::hash::Hasher>(&self, state: &mut H) {
+                        #(
+                            #cfgs
+                            ::core::hash::Hash::hash(self.#borrowers(), state);
+                        )*
+                    }
+                },
+            ),
+            "PartialOrd" => (
+                quote! { ::core::cmp::PartialOrd },
+                quote! {
+                    fn partial_cmp(
+                        &self,
+                        other: &Self,
+                    ) -> ::core::option::Option<::core::cmp::Ordering> {
+                        #(
+                            #cfgs
+                            match ::core::cmp::PartialOrd::partial_cmp(
+                                self.#borrowers(),
+                                other.#borrowers(),
+                            ) {
+                                ::core::option::Option::Some(::core::cmp::Ordering::Equal) => {}
+                                ordering => return ordering,
+                            }
+                        )*
+                        ::core::option::Option::Some(::core::cmp::Ordering::Equal)
+                    }
+                },
+            ),
+            "Ord" => (
+                quote! { ::core::cmp::Ord },
+                quote! {
+                    fn cmp(&self, other: &Self) -> ::core::cmp::Ordering {
+                        #(
+                            #cfgs
+                            match ::core::cmp::Ord::cmp(self.#borrowers(), other.#borrowers()) {
+                                ::core::cmp::Ordering::Equal => {}
+                                ordering => return ordering,
+                            }
+                        )*
+                        ::core::cmp::Ordering::Equal
+                    }
+                },
+            ),
+            _ => {
+                return Err(Error::new_spanned(
+                    trait_path,
+                    concat!(
+                        "Unsupported trait for derive(), expected one of PartialEq, Eq, Hash, ",
+                        "PartialOrd, or Ord."
+                    ),
+                ))
+            }
+        };
+        // Like the built-in derives, require every type parameter to implement the trait.
+        let mut bounded_params = generic_params.clone();
+        for param in bounded_params.type_params_mut() {
+            param.bounds.push(syn::parse_quote! { #bound });
         }
-    };
-    let documentation = concat!(
-        "This function drops all internally referencing fields and returns only the ",
-        "[head fields](https://docs.rs/ouroboros/latest/ouroboros/attr.self_referencing.html#definitions) of this struct."
-    ).to_owned();
+        // The borrowers may have been deprecated through accessor_attrs.
+        impls.push(quote! {
+            #[allow(deprecated)]
+            impl #bounded_params #bound for #struct_name <#(#generic_args),*> #generic_where {
+                #body
+            }
+        });
+    }
+    Ok(impls)
+}
 
-    let documentation = if !do_no_doc {
-        quote! {
-            #[doc=#documentation]
-        }
+/// Returns a Drop implementation which calls the function given by `on_drop`, if one was
+/// specified. The fields themselves are dropped afterwards in the order set up by
+/// create_actual_struct.
+fn make_drop_impl(
+    struct_name: &Ident,
+    generic_params: &Generics,
+    generic_args: &[TokenStream2],
+    options: &Options,
+) -> TokenStream2 {
+    let on_drop = if let Some(on_drop) = &options.on_drop {
+        on_drop
     } else {
-        quote! { #[doc(hidden)] }
+        return quote! {};
     };
-
-    let into_heads_fn = quote! {
-        #documentation
-        #[allow(clippy::drop_ref)]
-        #[allow(clippy::drop_copy)]
-        #visibility fn into_heads(self) -> Heads<#(#generic_args),*> {
-            #(#code)*
-            Heads {
-                #(#field_initializers),*
+    let generic_where = &generic_params.where_clause;
+    quote! {
+        impl #generic_params ::core::ops::Drop for #struct_name <#(#generic_args),*> #generic_where {
+            #[allow(deprecated)]
+            fn drop(&mut self) {
+                let _ = self.with_mut(|fields| #on_drop(fields));
             }
         }
-    };
-    (heads_struct_def, into_heads_fn)
+    }
 }
 
 fn make_type_asserts(
     field_info: &[StructFieldInfo],
     generic_params: &Generics,
     _generic_args: &[TokenStream2],
+    options: &Options,
 ) -> TokenStream2 {
+    let this = options.this_lifetime();
     let generic_where = &generic_params.where_clause;
     let mut checks = Vec::new();
     for field in field_info {
         let field_type = &field.typ;
         if let Some((std_type, _eltype)) = apparent_std_container_type(field_type) {
-            let checker_name = match std_type {
-                "Box" => "is_std_box_type",
-                "Arc" => "is_std_arc_type",
-                "Rc" => "is_std_rc_type",
-                _ => unreachable!(),
-            };
-            let checker_name = format_ident!("{}", checker_name);
+            let checker_name = format_ident!("is_std_{}_type", std_type.to_lowercase());
             let static_field_type =
-                replace_this_with_lifetime(quote! { #field_type }, format_ident!("static"));
+                replace_this_with_lifetime(quote! { #field_type }, &this, format_ident!("static"));
+            let cfg = field.cfg();
             checks.push(quote! {
+                #cfg
                 ::ouroboros::macro_help::CheckIfTypeIsStd::<#static_field_type>::#checker_name();
             });
         }
@@ -1491,6 +4349,56 @@ fn make_type_asserts(
     }
 }
 
+/// Returns the checks requested by assert_send and assert_sync and the implementations requested
+/// by unsafe_send and unsafe_sync. not_send and not_sync are handled by create_actual_struct.
+fn make_auto_trait_items(
+    struct_name: &Ident,
+    generic_params: &Generics,
+    generic_args: &[TokenStream2],
+    options: &Options,
+) -> TokenStream2 {
+    // The struct only has to implement the traits when its type parameters are Send and Sync,
+    // since a Box<T> head borrowed as &'this T needs both for the struct to be Send.
+    let mut thread_safe_params = generic_params.clone();
+    for param in thread_safe_params.type_params_mut() {
+        param.bounds.push(syn::parse_quote! { ::core::marker::Send });
+        param.bounds.push(syn::parse_quote! { ::core::marker::Sync });
+    }
+    let generic_where = &generic_params.where_clause;
+    let mut items = Vec::new();
+    let requests = [
+        (&options.send, quote! { ::core::marker::Send }, format_ident!("assert_send")),
+        (&options.sync, quote! { ::core::marker::Sync }, format_ident!("assert_sync")),
+    ];
+    for (request, auto_trait, checker_name) in &requests {
+        let request = if let Some(request) = request {
+            request.to_string()
+        } else {
+            continue;
+        };
+        if request.starts_with("assert_") {
+            items.push(quote! {
+                fn #checker_name #thread_safe_params() #generic_where {
+                    ::ouroboros::macro_help::#checker_name::<#struct_name <#(#generic_args),*>>();
+                }
+            });
+        } else if request.starts_with("unsafe_") {
+            // The fields are only vouched for as long as the values they were given are.
+            let mut bounded_params = generic_params.clone();
+            for param in bounded_params.type_params_mut() {
+                param.bounds.push(syn::parse_quote! { #auto_trait });
+            }
+            items.push(quote! {
+                unsafe impl #bounded_params #auto_trait for #struct_name <#(#generic_args),*>
+                #generic_where
+                {
+                }
+            });
+        }
+    }
+    quote! { #(#items)* }
+}
+
 fn submodule_contents_visiblity(original_visibility: &Visibility) -> Visibility {
     match original_visibility {
         // inherited: allow parent of inner submodule to see
@@ -1508,7 +4416,6 @@ fn submodule_contents_visiblity(original_visibility: &Visibility) -> Visibility
                 new_visibility.in_token = Some(
                     restricted
                         .in_token
-                        .clone()
                         .unwrap_or_else(|| syn::parse_quote! { in }),
                 );
                 new_visibility.path.segments = std::iter::once(syn::parse_quote! { super })
@@ -1526,17 +4433,17 @@ fn submodule_contents_visiblity(original_visibility: &Visibility) -> Visibility
 
 fn self_referencing_impl(
     original_struct_def: ItemStruct,
-    do_chain_hack: bool,
-    do_no_doc: bool,
-    do_pub_extras: bool,
-) -> Result<TokenStream, Error> {
+    options: Options,
+) -> Result<TokenStream2, Error> {
+    reject_const_generics(&original_struct_def.generics)?;
+    reject_lifetime_parameter_option(&original_struct_def.generics, &options)?;
     let struct_name = &original_struct_def.ident;
     let mod_name = format_ident!("ouroboros_impl_{}", struct_name.to_string().to_snake_case());
     let visibility = &original_struct_def.vis;
     let submodule_contents_visiblity = submodule_contents_visiblity(visibility);
 
     let (actual_struct_def, fake_lifetime, field_info) =
-        create_actual_struct(&submodule_contents_visiblity, &original_struct_def)?;
+        create_actual_struct(&submodule_contents_visiblity, &original_struct_def, &options)?;
 
     let generic_params = original_struct_def.generics.clone();
     let generic_args = make_generic_arguments(&generic_params);
@@ -1544,61 +4451,89 @@ fn self_referencing_impl(
     let builder_struct_name = format_ident!("{}Builder", struct_name);
     let (builder_def, constructor_def) = create_builder_and_constructor(
         &submodule_contents_visiblity,
-        &struct_name,
+        struct_name,
         &builder_struct_name,
         &fake_lifetime,
         &generic_params,
         &generic_args,
         &field_info[..],
-        do_chain_hack,
-        do_no_doc,
-        do_pub_extras,
+        &options,
         false,
     )?;
     let async_builder_struct_name = format_ident!("{}AsyncBuilder", struct_name);
     let (async_builder_def, async_constructor_def) = create_builder_and_constructor(
         &submodule_contents_visiblity,
-        &struct_name,
+        struct_name,
         &async_builder_struct_name,
         &fake_lifetime,
         &generic_params,
         &generic_args,
         &field_info[..],
-        do_chain_hack,
-        do_no_doc,
-        do_pub_extras,
+        &options,
         true,
     )?;
     let try_builder_struct_name = format_ident!("{}TryBuilder", struct_name);
     let (try_builder_def, try_constructor_def) = create_try_builder_and_constructor(
         &submodule_contents_visiblity,
-        &struct_name,
+        struct_name,
         &try_builder_struct_name,
         &fake_lifetime,
         &generic_params,
         &generic_args,
         &field_info[..],
-        do_chain_hack,
-        do_no_doc,
-        do_pub_extras,
+        &options,
         false,
     )?;
     let async_try_builder_struct_name = format_ident!("{}AsyncTryBuilder", struct_name);
     let (async_try_builder_def, async_try_constructor_def) = create_try_builder_and_constructor(
         &submodule_contents_visiblity,
-        &struct_name,
+        struct_name,
         &async_try_builder_struct_name,
         &fake_lifetime,
         &generic_params,
         &generic_args,
         &field_info[..],
-        do_chain_hack,
-        do_no_doc,
-        do_pub_extras,
+        &options,
         true,
     )?;
+    let (context_builder_def, context_constructor_def) = create_context_builders_and_constructors(
+        &submodule_contents_visiblity,
+        struct_name,
+        &fake_lifetime,
+        &generic_params,
+        &generic_args,
+        &field_info[..],
+        &options,
+    )?;
+    let (default_builder_def, default_impl) = make_default_builder(
+        &submodule_contents_visiblity,
+        struct_name,
+        &fake_lifetime,
+        &generic_params,
+        &generic_args,
+        &field_info[..],
+        &options,
+    )?;
+    let (stage_defs, start_fn) = make_stage_builders(
+        &submodule_contents_visiblity,
+        struct_name,
+        &fake_lifetime,
+        &generic_params,
+        &generic_args,
+        &field_info[..],
+        &options,
+    )?;
+
+    let build_error_def = make_build_error_enum(
+        &submodule_contents_visiblity,
+        struct_name,
+        &field_info,
+        &generic_params,
+        &generic_args,
+        &options,
+    )?;
 
-    let users = make_with_functions(&field_info[..], do_no_doc)?;
+    let users = make_with_functions(&field_info[..], &options)?;
     let (with_all_struct_defs, with_all_fn_defs) = make_with_all_function(
         &submodule_contents_visiblity,
         struct_name,
@@ -1606,8 +4541,7 @@ fn self_referencing_impl(
         &field_info[..],
         &generic_params,
         &generic_args,
-        do_no_doc,
-        do_pub_extras,
+        &options,
     )?;
     let (heads_struct_def, into_heads_fn) = make_into_heads(
         &submodule_contents_visiblity,
@@ -1615,21 +4549,76 @@ fn self_referencing_impl(
         &field_info[..],
         &generic_params,
         &generic_args,
-        do_no_doc,
-        do_pub_extras,
+        &options,
     );
+    let (view_defs, share_fns) = make_share_functions(
+        struct_name,
+        &fake_lifetime,
+        &field_info,
+        &generic_params,
+        &generic_args,
+        &options,
+    )?;
+    let deref_impl =
+        make_deref_impl(struct_name, &field_info, &generic_params, &generic_args, &options)?;
+    let delegate_impls = make_delegate_impls(
+        struct_name,
+        &field_info,
+        &generic_params,
+        &generic_args,
+        &options,
+    )?;
+    let compare_impls = make_compare_impls(
+        struct_name,
+        &field_info,
+        &generic_params,
+        &generic_args,
+        &options,
+    )?;
+    let drop_impl = make_drop_impl(struct_name, &generic_params, &generic_args, &options);
     // These check that types like Box, Arc, and Rc refer to those types in the std lib and have not
     // been overridden.
-    let type_asserts_def = make_type_asserts(&field_info[..], &generic_params, &generic_args);
+    let type_asserts_def =
+        make_type_asserts(&field_info[..], &generic_params, &generic_args, &options);
+    let auto_trait_items =
+        make_auto_trait_items(struct_name, &generic_params, &generic_args, &options);
 
-    let extra_visibility = if do_pub_extras {
-        visibility.clone()
+    let builder_visibility = options.reexport_visibility(&options.vis.builder, visibility);
+    let view_uses = field_info.iter().filter(|field| field.share).map(|field| {
+        let view_name = field.view_name(struct_name);
+        let view_visibility = &field.declared_vis;
+        let cfg = field.cfg();
+        quote! { #cfg #view_visibility use #mod_name :: #view_name; }
+    });
+    let build_error_use = if options.do_per_field_errors {
+        let build_error_name = format_ident!("{}BuildError", struct_name);
+        let build_error_visibility = options.reexport_visibility(&options.vis.new, visibility);
+        quote! { #build_error_visibility use #mod_name :: #build_error_name; }
+    } else {
+        quote! {}
+    };
+    let default_builder_use = if field_info.iter().any(|field| field.default.is_some()) {
+        let default_builder_struct_name = format_ident!("{}DefaultBuilder", struct_name);
+        quote! { #builder_visibility use #mod_name :: #default_builder_struct_name; }
+    } else {
+        quote! {}
+    };
+    let stage_count = if has_cfg_fields(&field_info) {
+        0
     } else {
-        syn::Visibility::Inherited
+        field_info.len()
     };
+    let stage_names = (1..=stage_count).map(|index| stage_name(struct_name, index));
+
+    let heads_builder_struct_name = heads_builder_name(&builder_struct_name);
+    let async_heads_builder_struct_name = heads_builder_name(&async_builder_struct_name);
+    let try_heads_builder_struct_name = heads_builder_name(&try_builder_struct_name);
+    let async_try_heads_builder_struct_name = heads_builder_name(&async_try_builder_struct_name);
+    let context_builder_struct_name = format_ident!("{}BuilderWithContext", struct_name);
+    let try_context_builder_struct_name = format_ident!("{}TryBuilderWithContext", struct_name);
 
     let generic_where = &generic_params.where_clause;
-    Ok(TokenStream::from(quote! {
+    let mut expansion = quote! {
         #[doc="Encapsulates implementation details for a self-referencing struct. This module is only visible when using --document-private-items."]
         mod #mod_name {
             use super::*;
@@ -1638,76 +4627,163 @@ fn self_referencing_impl(
             #async_builder_def
             #try_builder_def
             #async_try_builder_def
+            #context_builder_def
+            #default_builder_def
+            #stage_defs
             #with_all_struct_defs
             #heads_struct_def
+            #build_error_def
+            #(#view_defs)*
             impl #generic_params #struct_name <#(#generic_args),*> #generic_where {
                 #constructor_def
                 #async_constructor_def
                 #try_constructor_def
                 #async_try_constructor_def
+                #context_constructor_def
+                #start_fn
                 #(#users)*
                 #with_all_fn_defs
                 #into_heads_fn
+                #(#share_fns)*
             }
+            #deref_impl
+            #(#delegate_impls)*
+            #(#compare_impls)*
+            #default_impl
+            #drop_impl
             #type_asserts_def
+            #auto_trait_items
         }
         #visibility use #mod_name :: #struct_name;
-        #extra_visibility use #mod_name :: #builder_struct_name;
-        #extra_visibility use #mod_name :: #async_builder_struct_name;
-        #extra_visibility use #mod_name :: #try_builder_struct_name;
-        #extra_visibility use #mod_name :: #async_try_builder_struct_name;
-    }))
+        #builder_visibility use #mod_name :: #builder_struct_name;
+        #builder_visibility use #mod_name :: #async_builder_struct_name;
+        #builder_visibility use #mod_name :: #try_builder_struct_name;
+        #builder_visibility use #mod_name :: #async_try_builder_struct_name;
+        #builder_visibility use #mod_name :: #heads_builder_struct_name;
+        #builder_visibility use #mod_name :: #async_heads_builder_struct_name;
+        #builder_visibility use #mod_name :: #try_heads_builder_struct_name;
+        #builder_visibility use #mod_name :: #async_try_heads_builder_struct_name;
+        #builder_visibility use #mod_name :: #context_builder_struct_name;
+        #builder_visibility use #mod_name :: #try_context_builder_struct_name;
+        #build_error_use
+        #default_builder_use
+        #(#builder_visibility use #mod_name :: #stage_names;)*
+        #(#view_uses)*
+    };
+    if options.do_debug_expand || debug_expand_requested_by_env() {
+        let note = write_debug_expansion(&mod_name, &expansion);
+        expansion.extend(note);
+    }
+    Ok(expansion)
+}
+
+/// The environment variable which turns on debug_expand for every struct in a crate.
+const DEBUG_EXPAND_VAR: &str = "OUROBOROS_DEBUG_EXPAND";
+
+fn debug_expand_requested_by_env() -> bool {
+    matches!(std::env::var_os(DEBUG_EXPAND_VAR), Some(value) if !value.is_empty() && value != "0")
+}
+
+/// Writes the expansion of the macro to a file so that it can be inspected when it fails to
+/// compile. Returns an item which produces a warning pointing at the file. Procedural macros can
+/// only emit warnings on nightly, so the warning comes from using an item marked #[deprecated].
+fn write_debug_expansion(mod_name: &Ident, expansion: &TokenStream2) -> TokenStream2 {
+    let message = match try_write_debug_expansion(mod_name, expansion) {
+        Ok(path) => format!("ouroboros wrote the expansion of this struct to {}", path.display()),
+        Err(err) => format!("ouroboros could not write the expansion of this struct: {}", err),
+    };
+    quote! {
+        const _: () = {
+            #[deprecated(note = #message)]
+            struct DebugExpand;
+            let _ = DebugExpand;
+        };
+    }
+}
+
+fn try_write_debug_expansion(
+    mod_name: &Ident,
+    expansion: &TokenStream2,
+) -> std::io::Result<std::path::PathBuf> {
+    let dir = debug_expansion_dir().join("ouroboros_expanded");
+    std::fs::create_dir_all(&dir)?;
+    let file_name = debug_expansion_file_name(mod_name);
+    let path = dir.join(file_name);
+    let source = expansion.to_string();
+    let source = rustfmt(&source).unwrap_or(source);
+    std::fs::write(&path, source)?;
+    Ok(path)
+}
+
+/// Returns the name of the file holding the expansion of the module `mod_name`. Structs with the
+/// same name in different modules of a crate are expanded by the same compiler process, which
+/// numbers them in the order they are expanded so that they do not overwrite each other's file.
+fn debug_expansion_file_name(mod_name: &Ident) -> String {
+    static EXPANDED: std::sync::Mutex<Vec<String>> = std::sync::Mutex::new(Vec::new());
+    let base_name = match std::env::var("CARGO_CRATE_NAME") {
+        Ok(crate_name) => format!("{}_{}", crate_name, mod_name),
+        Err(_) => mod_name.to_string(),
+    };
+    let mut expanded = EXPANDED.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let earlier = expanded.iter().filter(|name| **name == base_name).count();
+    expanded.push(base_name.clone());
+    if earlier == 0 {
+        format!("{}.rs", base_name)
+    } else {
+        format!("{}_{}.rs", base_name, earlier + 1)
+    }
+}
+
+/// Returns OUT_DIR if the crate has a build script, and the target directory otherwise. Cargo does
+/// not tell procedural macros where the target directory is unless CARGO_TARGET_DIR is set, so
+/// it is looked for next to the manifest of the crate and of the workspace it is part of.
+fn debug_expansion_dir() -> std::path::PathBuf {
+    if let Some(out_dir) = std::env::var_os("OUT_DIR") {
+        return out_dir.into();
+    }
+    if let Some(target_dir) = std::env::var_os("CARGO_TARGET_DIR") {
+        return target_dir.into();
+    }
+    let manifest_dir: std::path::PathBuf = std::env::var_os("CARGO_MANIFEST_DIR")
+        .unwrap_or_else(|| ".".into())
+        .into();
+    manifest_dir
+        .ancestors()
+        .map(|dir| dir.join("target"))
+        .find(|dir| dir.is_dir())
+        .unwrap_or_else(|| manifest_dir.join("target"))
+}
+
+/// Formats the given source code with rustfmt. Returns None if rustfmt is not installed or fails,
+/// in which case the unformatted source is written instead.
+fn rustfmt(source: &str) -> Option<String> {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+    let rustfmt = std::env::var_os("RUSTFMT").unwrap_or_else(|| "rustfmt".into());
+    let mut child = Command::new(rustfmt)
+        .args(["--edition", "2018"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    // rustfmt reads all of its input before writing anything, so this cannot deadlock.
+    child.stdin.take()?.write_all(source.as_bytes()).ok()?;
+    let output = child.wait_with_output().ok()?;
+    if output.status.success() {
+        String::from_utf8(output.stdout).ok()
+    } else {
+        None
+    }
 }
 
 #[proc_macro_error]
 #[proc_macro_attribute]
 pub fn self_referencing(attr: TokenStream, item: TokenStream) -> TokenStream {
-    let mut do_chain_hack = false;
-    let mut do_no_doc = false;
-    let mut do_pub_extras = false;
-    let mut expecting_comma = false;
-    for token in <TokenStream as std::convert::Into<TokenStream2>>::into(attr).into_iter() {
-        if let TokenTree::Ident(ident) = &token {
-            if expecting_comma {
-                return Error::new(token.span(), "Unexpected identifier, expected comma.")
-                    .to_compile_error()
-                    .into();
-            }
-            match &ident.to_string()[..] {
-                "chain_hack" => do_chain_hack = true,
-                "no_doc" => do_no_doc = true,
-                "pub_extras" => do_pub_extras = true,
-                _ => {
-                    return Error::new_spanned(
-                        &ident,
-                        "Unknown identifier, expected 'chain_hack', 'no_doc', or 'pub_extras'.",
-                    )
-                    .to_compile_error()
-                    .into()
-                }
-            }
-            expecting_comma = true;
-        } else if let TokenTree::Punct(punct) = &token {
-            if !expecting_comma {
-                return Error::new(token.span(), "Unexpected punctuation, expected identifier.")
-                    .to_compile_error()
-                    .into();
-            }
-            if punct.as_char() != ',' {
-                return Error::new(token.span(), "Unknown punctuation, expected comma.")
-                    .to_compile_error()
-                    .into();
-            }
-            expecting_comma = false;
-        } else {
-            return Error::new(token.span(), "Unknown syntax, expected identifier.")
-                .to_compile_error()
-                .into();
-        }
-    }
+    let options = syn::parse_macro_input!(attr as Options);
     let original_struct_def: ItemStruct = syn::parse_macro_input!(item);
-    match self_referencing_impl(original_struct_def, do_chain_hack, do_no_doc, do_pub_extras) {
-        Ok(content) => content,
+    match self_referencing_impl(original_struct_def, options) {
+        Ok(content) => content.into(),
         Err(err) => err.to_compile_error().into(),
     }
 }
@@ -1727,3 +4803,132 @@ fn to_class_case(s: &str) -> String {
         })
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs the macro outside of the compiler and parses its output.
+    fn expand(options: TokenStream2, item: TokenStream2) -> syn::File {
+        let options: Options = syn::parse2(options).unwrap();
+        let item: ItemStruct = syn::parse2(item).unwrap();
+        let mut expansion = None;
+        // An empty proc_macro::TokenStream is the only one which can be created outside of a
+        // procedural macro.
+        let run = std::panic::AssertUnwindSafe(|| {
+            expansion = Some(self_referencing_impl(item, options));
+            TokenStream::new()
+        });
+        proc_macro_error::entry_point(run, false);
+        syn::parse2(expansion.unwrap().unwrap()).unwrap()
+    }
+
+    fn doc_lines(attrs: &[Attribute]) -> Vec<String> {
+        attrs
+            .iter()
+            .filter_map(|attr| match attr.parse_meta() {
+                Ok(syn::Meta::NameValue(syn::MetaNameValue {
+                    path,
+                    lit: syn::Lit::Str(lit),
+                    ..
+                })) if path.is_ident("doc") => Some(lit.value().trim().to_owned()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns the names of the generated structs, fields and methods which have the given line in
+    /// their documentation, like `Heads.data` or `Documented::borrow_data`.
+    fn documented_with(items: &[syn::Item], line: &str, found: &mut Vec<String>) {
+        for item in items {
+            match item {
+                syn::Item::Mod(module) => {
+                    if let Some((_, items)) = &module.content {
+                        documented_with(items, line, found);
+                    }
+                }
+                syn::Item::Struct(item_struct) => {
+                    for field in &item_struct.fields {
+                        if doc_lines(&field.attrs).iter().any(|doc| doc == line) {
+                            let field_name = field.ident.as_ref().unwrap();
+                            found.push(format!("{}.{}", item_struct.ident, field_name));
+                        }
+                    }
+                }
+                syn::Item::Impl(item_impl) => {
+                    let self_ty = &item_impl.self_ty;
+                    let self_name = match &**self_ty {
+                        Type::Path(path) => path.path.segments.last().unwrap().ident.to_string(),
+                        _ => continue,
+                    };
+                    for impl_item in &item_impl.items {
+                        if let syn::ImplItem::Method(method) = impl_item {
+                            if doc_lines(&method.attrs).iter().any(|doc| doc == line) {
+                                found.push(format!("{}::{}", self_name, method.sig.ident));
+                            }
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn expand_documented(options: TokenStream2) -> syn::File {
+        expand(
+            options,
+            quote! {
+                struct Documented {
+                    /// Owned data.
+                    data: Box<i32>,
+                    /// A reference to the data.
+                    #[borrows(data)]
+                    data_ref: &'this i32,
+                }
+            },
+        )
+    }
+
+    #[test]
+    fn field_docs_are_copied() {
+        let file = expand_documented(quote! {});
+        let mut head_items = Vec::new();
+        documented_with(&file.items, "Owned data.", &mut head_items);
+        for expected in &[
+            "Documented::with_data",
+            "Documented::borrow_data",
+            "DocumentedBuilder.data",
+            "DocumentedTryBuilder.data",
+            "BorrowedFields.data",
+            "Heads.data",
+            "DocumentedStage1::with_data",
+            "DocumentedStage1::borrow_data",
+        ] {
+            assert!(head_items.iter().any(|item| item == expected), "{:?}", head_items);
+        }
+        let mut tail_items = Vec::new();
+        documented_with(&file.items, "A reference to the data.", &mut tail_items);
+        for expected in &[
+            "Documented::with_data_ref",
+            "Documented::borrow_data_ref",
+            "DocumentedBuilder.data_ref_builder",
+            "DocumentedTryBuilder.data_ref_builder",
+            "BorrowedFields.data_ref",
+            "DocumentedStage2::with_data_ref",
+        ] {
+            assert!(tail_items.iter().any(|item| item == expected), "{:?}", tail_items);
+        }
+    }
+
+    #[test]
+    fn field_docs_are_not_copied_with_no_doc() {
+        let file = expand_documented(quote! { no_doc });
+        for line in &["Owned data.", "A reference to the data."] {
+            let mut items = Vec::new();
+            documented_with(&file.items, line, &mut items);
+            // The fields of the struct itself keep the doc comments they were written with.
+            items.retain(|item| !item.starts_with("Documented."));
+            assert_eq!(items, Vec::<String>::new());
+        }
+    }
+}